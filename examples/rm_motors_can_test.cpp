@@ -63,16 +63,14 @@ extern "C" int rm_motors_can_test_cpp() {
         shared_final.store(true);
     });
 
-    // Start another thread to periodically collect feedbacks and write commands
+    // Have the library periodically collect feedbacks and write commands on its own thread
     // It's better to run_once() after every set_cmd to minimize delay before writing,
     // but if this loop is fast enough it will not be noticeable. This approach has the advantage of
     // running consistently, which prevents the socket buffer from filling up in case e.g. the main thread is blocked.
-    threads.emplace_back(std::thread([](){
-        while (!shared_stop.load()) {
-            rm_motors_can::run_once(gmc);
-            std::this_thread::sleep_for(std::chrono::milliseconds(INC));
-        }
-    }));
+    if (rm_motors_can::start_loop(gmc, INC*1000)<0){
+        std::cerr<<"Error starting the I/O loop"<<std::endl;
+        return -1;
+    }
 
     // Ramp up, ramp down, ramp up (negative), ramp down (negative)
     for (int voltage = 0; voltage <= MAX; voltage += 2) {
@@ -106,13 +104,16 @@ extern "C" int rm_motors_can_test_cpp() {
     // Join all threads so it doesn't complain about unfinished business
     for (std::thread & thread : threads)
         thread.join();
+    rm_motors_can::free_bus(gmc);
 
     return 0;
 }
 
 // Print out a simple bar chart of feedback values
 void print_output(rm_motors_can::RmMotorsCan* rm_motors_can) {
-    double val = rm_motors_can::get_state(rm_motors_can, ID, FB_FIELD);
+    double val = 0.0;
+    if (rm_motors_can::get_state(rm_motors_can, ID, FB_FIELD, &val) != 0)
+        return;
      // Right justify, 7 wide, 2 decimal digits
     std::cout<<std::fixed<<std::setprecision(2)<<std::right<<std::setw(7)<<val<<std::left<<std::setw(0)<<"\t";
     unsigned int n = 0;
@@ -129,6 +130,9 @@ void print_output(rm_motors_can::RmMotorsCan* rm_motors_can) {
         case rm_motors_can::FbField::Temperature:
             n = val;
             break;
+        case rm_motors_can::FbField::MultiTurn:
+            n = abs(val*5.0);
+            break;
     }
     std::cout<<std::string(n, '#')<<std::endl;
 }
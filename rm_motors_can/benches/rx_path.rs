@@ -0,0 +1,150 @@
+use rm_motors_can::protocol::{self, Feedback};
+use rm_motors_can::{CanTransport, FbField, LoopbackTransport, MotorType, RmMotorsCan};
+use embedded_can::{Frame as EmbeddedFrame, StandardId};
+use socketcan::CanFrame;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//////
+// Compares the receive path before and after moving it to a dedicated thread.
+//
+// A simulated GM6020 sends feedback at 1kHz over a LoopbackTransport, encoding a sequence number in the position.
+// A "user" thread calls run_once at a fixed period while another thread samples the position as an application would,
+// and looks up how long ago that feedback was sent.
+//
+// before: run_once drained the transport with 1us timeouts, so feedback was only as fresh as the last run_once.
+//         Emulated here since that code path no longer exists.
+// after:  the library's receive thread blocks on the transport and updates feedback as frames arrive.
+//
+// CPU is the process CPU time per second of wall time, minus a baseline of the motor and sampler threads alone.
+//////
+/*
+cargo bench --bench rx_path
+*/
+
+const RUN_TIME: Duration = Duration::from_secs(2);
+const PERIODS_US: [u64; 3] = [10_000, 1_000, 100]; // run_once period
+const POS_MAX: f64 = 8191.0;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Path { Baseline, Before, After }
+
+struct Result {
+    cpu:      f64, // percent of one core
+    mean_age: Duration,
+    max_age:  Duration,
+}
+
+fn cpu_time() -> Duration {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+fn feedback_frame(seq: u16) -> CanFrame {
+    let id: StandardId = StandardId::new(protocol::feedback_id(MotorType::GM6020, 1)).unwrap();
+    CanFrame::new(id, &Feedback { position: seq, temperature: 25, ..Default::default() }.encode()).unwrap()
+}
+
+fn run(path: Path, period_us: u64) -> Result {
+    let (lib_end, motor_end) = LoopbackTransport::pair();
+    let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let epoch: Instant = Instant::now();
+    // Nanoseconds since epoch that each position was sent at
+    let sent: Arc<Vec<AtomicU64>> = Arc::new((0 .. POS_MAX as usize + 1).map(|_| AtomicU64::new(0)).collect());
+
+    // The motor
+    let motor = {
+        let (stop, sent) = (stop.clone(), sent.clone());
+        thread::spawn(move || {
+            let mut next: Instant = Instant::now();
+            let mut seq: u16 = 0;
+            while !stop.load(Ordering::Relaxed) {
+                sent[seq as usize].store(epoch.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                motor_end.send(&feedback_frame(seq)).unwrap();
+                seq = (seq + 1) % (POS_MAX as u16 + 1);
+                next += Duration::from_millis(1);
+                thread::sleep(next.saturating_duration_since(Instant::now()));
+            }
+        })
+    };
+
+    // The receive path under test, keeping track of the latest position
+    let latest: Arc<Mutex<u16>> = Arc::new(Mutex::new(0));
+    let mut user: Option<thread::JoinHandle<()>> = None;
+    let mut rm: Option<Arc<RmMotorsCan>> = None;
+    match path {
+        Path::Baseline => {
+            // Read frames so the loopback queue doesn't grow, but don't count it
+            let latest = latest.clone();
+            let stop = stop.clone();
+            thread::spawn(move || while !stop.load(Ordering::Relaxed) {
+                if let Ok(Some(frame)) = lib_end.recv_timeout(Duration::from_millis(100)) {
+                    *latest.lock().unwrap() = Feedback::decode(frame.data()).unwrap().position;
+                }
+            });
+        },
+        Path::Before => {
+            let (latest, stop) = (latest.clone(), stop.clone());
+            user = Some(thread::spawn(move || while !stop.load(Ordering::Relaxed) {
+                // The old rx_fb
+                while let Some(frame) = lib_end.recv_timeout(Duration::from_micros(1)).unwrap() {
+                    *latest.lock().unwrap() = Feedback::decode(frame.data()).unwrap().position;
+                }
+                thread::sleep(Duration::from_micros(period_us));
+            }));
+        },
+        Path::After => {
+            let handle: Arc<RmMotorsCan> = RmMotorsCan::with_transport(Box::new(lib_end)).unwrap();
+            rm = Some(handle.clone());
+            let stop = stop.clone();
+            user = Some(thread::spawn(move || while !stop.load(Ordering::Relaxed) {
+                handle.run_once().unwrap();
+                thread::sleep(Duration::from_micros(period_us));
+            }));
+        },
+    }
+
+    // The application, sampling the position
+    let cpu_start: Duration = cpu_time();
+    let start: Instant = Instant::now();
+    let (mut total, mut max, mut samples): (Duration, Duration, u32) = (Duration::ZERO, Duration::ZERO, 0);
+    while start.elapsed() < RUN_TIME {
+        thread::sleep(Duration::from_micros(300));
+        let position: u16 = match &rm {
+            Some(rm) => (rm.get_state(1, FbField::Position).unwrap()/(2.0*PI)*POS_MAX).round() as u16,
+            None => *latest.lock().unwrap(),
+        };
+        let sent_at: u64 = sent[position as usize].load(Ordering::Relaxed);
+        if sent_at == 0 {continue;}
+        let age: Duration = epoch.elapsed().saturating_sub(Duration::from_nanos(sent_at));
+        total += age;
+        max = max.max(age);
+        samples += 1;
+    }
+    let cpu: Duration = cpu_time() - cpu_start;
+    let wall: Duration = start.elapsed();
+
+    stop.store(true, Ordering::Relaxed);
+    motor.join().unwrap();
+    if let Some(user) = user { user.join().unwrap(); }
+    Result { cpu: 100.0*cpu.as_secs_f64()/wall.as_secs_f64(), mean_age: total/samples.max(1), max_age: max }
+}
+
+fn main() {
+    let baseline: Result = run(Path::Baseline, 0);
+    println!("Baseline CPU (motor and sampler threads): {:.1}%\n", baseline.cpu);
+    println!("| path   | run_once period | CPU    | mean feedback age | max feedback age |");
+    println!("|--------|-----------------|--------|-------------------|------------------|");
+    for period_us in PERIODS_US {
+        for (name, path) in [("before", Path::Before), ("after", Path::After)] {
+            let r: Result = run(path, period_us);
+            let period: String = format!("{} ms", period_us as f64/1000.0);
+            println!("| {:<6} | {:<15} | {:>5.1}% | {:>14.3} ms | {:>13.3} ms |", name, period, (r.cpu - baseline.cpu).max(0.0),
+                r.mean_age.as_secs_f64()*1e3, r.max_age.as_secs_f64()*1e3);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rm_motors_can::{CmdMode, FbField, MotorType, RmMotorsCan, Stage, StageSignal};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+//////
+// Time of the control path (set_cmd + run_once) while other threads read the state as fast as they can,
+// as a UI or logger would. The receive thread is also updating feedback from the simulated motors throughout.
+// Also the time of a single update of one motor's snapshot (copying the motor and publishing the copy), which is what the
+// receive thread pays for each feedback frame.
+//////
+/*
+cargo bench --bench state_contention
+*/
+
+const READERS: [usize; 3] = [0, 2, 4];
+
+fn control_path(c: &mut Criterion) {
+    let rm: Arc<RmMotorsCan> = RmMotorsCan::builder()
+        .interface("sim:GM6020:1,M3508:2")
+        .motor(1, MotorType::GM6020, CmdMode::Velocity)
+        .motor(2, MotorType::M3508, CmdMode::Current)
+        .build().unwrap();
+
+    let mut group = c.benchmark_group("set_cmd + run_once");
+    for readers in READERS {
+        let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+        let threads: Vec<thread::JoinHandle<()>> = (0 .. readers).map(|_| {
+            let (rm, stop) = (rm.clone(), stop.clone());
+            thread::spawn(move || while !stop.load(Ordering::Relaxed) {
+                for field in [FbField::Position, FbField::Velocity, FbField::Current, FbField::MultiTurn] {
+                    black_box(rm.get_state(1, field).unwrap());
+                }
+                black_box(rm.get_stage_signal(1, Stage::Velocity, StageSignal::Output).unwrap());
+            })
+        }).collect();
+
+        group.bench_with_input(BenchmarkId::new("readers", readers), &readers, |b, _| {
+            let mut sign: f64 = 1.0;
+            b.iter(|| {
+                // Alternate the commands so every iteration changes the state
+                sign = -sign;
+                rm.set_cmd(1, sign*10.0).unwrap();
+                rm.set_cmd(2, sign*1.0).unwrap();
+                rm.run_once().unwrap();
+            })
+        });
+
+        stop.store(true, Ordering::Relaxed);
+        for t in threads { t.join().unwrap(); }
+    }
+    group.finish();
+    rm.cleanup(0).unwrap();
+}
+
+// zero_position is one update of the snapshot with next to nothing else, like applying a feedback frame
+fn snapshot_update(c: &mut Criterion) {
+    let rm: Arc<RmMotorsCan> = RmMotorsCan::builder()
+        .interface("sim:GM6020:1")
+        .motor(1, MotorType::GM6020, CmdMode::Velocity)
+        .build().unwrap();
+    c.bench_function("snapshot update", |b| b.iter(|| rm.zero_position(1).unwrap()));
+    rm.cleanup(0).unwrap();
+}
+
+criterion_group!(benches, control_path, snapshot_update);
+criterion_main!(benches);
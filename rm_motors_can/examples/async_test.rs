@@ -0,0 +1,47 @@
+use rm_motors_can::{AsyncRmMotorsCan, CmdMode, FeedbackUpdate, MotorType};
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+//////
+// Example showing how to use the async API of rm_motors_can from a tokio application.
+//////
+/*
+cargo run --example async_test --features tokio
+*/
+
+const INC: u64 = 10;                               // Time (ms) between commands
+const ID: u8 = 1;                                  // Motor ID [1,7]
+const CAN_INTERFACE: &str = "can0";                // SocketCAN interface to open
+
+#[tokio::main]
+async fn main() {
+    // Open SocketCAN device
+    let gmc: AsyncRmMotorsCan = AsyncRmMotorsCan::open(CAN_INTERFACE).await.unwrap();
+    // Set up the motor
+    gmc.init_motor(ID, MotorType::GM6020, CmdMode::Voltage).map_or_else(|e| panic!("{}", e), |_| ());
+
+    // Print every 50th feedback from the motor as it arrives
+    let mut feedback = Box::pin(gmc.feedback().filter(|update: &FeedbackUpdate| update.id == ID));
+    let printer = tokio::spawn(async move {
+        let mut count: u64 = 0;
+        while let Some(update) = feedback.next().await {
+            if count.is_multiple_of(50) {
+                println!("{:>7.2} rad/s {:>5.1} °C", update.velocity, update.temperature);
+            }
+            count += 1;
+        }
+    });
+
+    // Have a task on the runtime write commands periodically
+    gmc.start_loop(INC*1000).map_or_else(|e| panic!("{}", e), |_| ());
+
+    // Ramp up to 10V then back down to 2V
+    for voltage in (0 .. 101).chain((20 .. 100).rev()) {
+        gmc.set_cmd(ID, voltage as f64 / 10f64).map_or_else(|e| eprintln!("{}", e), |_| ());
+        tokio::time::sleep(Duration::from_millis(INC)).await;
+    }
+
+    // Gently turn off the motor
+    gmc.cleanup(5).await.map_or_else(|e| eprintln!("{}", e), |_| ());
+    printer.abort();
+}
@@ -2,7 +2,6 @@ use rm_motors_can::{CmdMode, FbField, RmMotorsCan, MotorType};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::{self, JoinHandle};
 use std::sync::Arc;
-use ctrlc;
 
 //////
 // Example showing how to use rm_motors_can library.
@@ -19,9 +18,9 @@ const CAN_INTERFACE: &str = "can0";                // SocketCAN interface to ope
 
 fn main() {
     // Open SocketCAN device
-    let gmc: Arc<RmMotorsCan> = rm_motors_can::init_bus(CAN_INTERFACE).unwrap();
+    let gmc: Arc<RmMotorsCan> = RmMotorsCan::open(CAN_INTERFACE).unwrap();
     // Set up the motor
-    rm_motors_can::init_motor(gmc.clone(), ID, MotorType::GM6020, CmdMode::Voltage).map_or_else(|e| panic!("{}", e), |_| ());
+    gmc.init_motor(ID, MotorType::GM6020, CmdMode::Voltage).map_or_else(|e| panic!("{}", e), |_| ());
 
     // Atomic flag to trigger stopping the threads
     let shared_stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
@@ -32,7 +31,7 @@ fn main() {
     let _dbg: JoinHandle<()> = thread::spawn( move ||
         while ! shared_stop_ref2.load(Ordering::Relaxed){
             thread::sleep(std::time::Duration::from_millis(50));
-            print_output(gmc_ref2.clone());
+            print_output(&gmc_ref2);
         }
     );
 
@@ -45,48 +44,41 @@ fn main() {
         // stop the other threads
         shared_stop_ref3.store(true, Ordering::Relaxed);
         // gently turn off the motors
-        rm_motors_can::cleanup(gmc_ref3.clone(), 5).map_or_else(|e| eprintln!("{}", e), |_| ());
+        gmc_ref3.cleanup(5).map_or_else(|e| eprintln!("{}", e), |_| ());
         // stop this thread
         shared_final_ref2.store(true, Ordering::Relaxed);
     });
 
-    // Start another thread to periodically collect feedbacks and write commands
+    // Have the library periodically collect feedbacks and write commands on its own thread
     // It's better to run_once() after every set_cmd to minimize delay before writing,
     // but if this loop is fast enough it will not be noticeable. This approach has the advantage of
     // running consistently, which prevents the socket buffer from filling up in case e.g. the main thread is blocked.
-    let shared_stop_ref4: Arc<AtomicBool> = shared_stop.clone();
-    let gmc_ref4: Arc<RmMotorsCan> = gmc.clone();
-    thread::spawn( move ||
-        while ! shared_stop_ref4.load(Ordering::Relaxed) {
-            rm_motors_can::run_once(gmc_ref4.clone()).map_or_else(|e| eprintln!("{}", e), |_| ());
-            thread::sleep(std::time::Duration::from_millis(INC));
-        }
-    );
+    gmc.start_loop(INC*1000).map_or_else(|e| panic!("{}", e), |_| ());
 
     // Ramp up, ramp down, ramp up (negative), ramp down (negative)
     for voltage in (0 .. MAX+1).step_by(2) {
         if shared_stop.load(Ordering::Relaxed) {break;} // Check if the ctl-c handler was called
-        rm_motors_can::set_cmd(gmc.clone(), ID, voltage as f64 / 10f64).map_or_else(|e| eprintln!("{}", e), |_| ());
+        gmc.set_cmd(ID, voltage as f64 / 10f64).map_or_else(|e| eprintln!("{}", e), |_| ());
         thread::sleep(std::time::Duration::from_millis(INC));
     }
     for voltage in (0 .. MAX).rev().step_by(2) {
         if shared_stop.load(Ordering::Relaxed) {break;} // Check if the ctl-c handler was called
-        rm_motors_can::set_cmd(gmc.clone(), ID, voltage as f64 / 10f64).map_or_else(|e| eprintln!("{}", e), |_| ());
+        gmc.set_cmd(ID, voltage as f64 / 10f64).map_or_else(|e| eprintln!("{}", e), |_| ());
         thread::sleep(std::time::Duration::from_millis(INC));
     }
-    for voltage in (-1*MAX .. 0).rev().step_by(2) {
+    for voltage in (-MAX .. 0).rev().step_by(2) {
         if shared_stop.load(Ordering::Relaxed) {break;} // Check if the ctl-c handler was called
-        rm_motors_can::set_cmd(gmc.clone(), ID, voltage as f64 / 10f64).map_or_else(|e| eprintln!("{}", e), |_| ());
+        gmc.set_cmd(ID, voltage as f64 / 10f64).map_or_else(|e| eprintln!("{}", e), |_| ());
         thread::sleep(std::time::Duration::from_millis(INC));
     }
-    for voltage in (-1*MAX+1 .. 1).step_by(2) {
+    for voltage in (-MAX+1 .. 1).step_by(2) {
         if shared_stop.load(Ordering::Relaxed) {break;} // Check if the ctl-c handler was called
-        rm_motors_can::set_cmd(gmc.clone(), ID, voltage as f64 / 10f64).map_or_else(|e| eprintln!("{}", e), |_| ());
+        gmc.set_cmd(ID, voltage as f64 / 10f64).map_or_else(|e| eprintln!("{}", e), |_| ());
         thread::sleep(std::time::Duration::from_millis(INC));
     }
 
     // Send one last voltage command
-    rm_motors_can::set_cmd(gmc.clone(), ID, 2f64).map_or_else(|e| eprintln!("{}", e), |_| ());
+    gmc.set_cmd(ID, 2f64).map_or_else(|e| eprintln!("{}", e), |_| ());
     // Wait for the ctl-c handler to finish cleaning up
     while ! shared_final.load(Ordering::Relaxed){
         thread::sleep(std::time::Duration::from_millis(50));
@@ -94,13 +86,14 @@ fn main() {
 }
 
 // Print out a simple bar chart of feedback values
-fn print_output(rm_motors_can: Arc<RmMotorsCan>) {
-    let val = rm_motors_can::get_state(rm_motors_can, ID, FB_FIELD).unwrap();
+fn print_output(rm_motors_can: &RmMotorsCan) {
+    let val = rm_motors_can.get_state(ID, FB_FIELD).unwrap();
     print!("{val:>7.2}\t"); // Right justify, 7 wide, 2 decimal digits
     println!("{:#<1$}", "", match FB_FIELD {
         FbField::Position    => (val*5f64) as usize,
         FbField::Velocity    => val.abs() as usize,
         FbField::Current     => (val.abs()*10f64) as usize,
         FbField::Temperature => val as usize,
+        FbField::MultiTurn   => (val.abs()*5f64) as usize,
     })
 }
\ No newline at end of file
@@ -0,0 +1,284 @@
+use crate::builder::{sim_spec, LISTEN_TIME};
+use crate::bus::{feedback_filter, instant, zero_frame, Commands};
+use crate::transport::enable_timestamps;
+use crate::motor::{self, Motor};
+use crate::protocol::{is_command_id, CMD_IDS};
+use crate::{lock, CanTransport, CmdMode, Fault, FbField, FrameTimestamps, MotorType, RmError, RmMotorsCan, SimBus};
+use crate::{ARR_LEN, RAMP_STEP, RX_TIMEOUT};
+use socketcan::tokio::CanSocket;
+use socketcan::{CanFilter, CanFrame, Frame, SocketOptions};
+use std::io;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+const UPDATES: usize = 256; // Feedback updates buffered for each subscriber, beyond which a slow one misses the oldest
+
+// One motor's feedback as it arrived, in the units of get_state
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FeedbackUpdate {
+    pub id:          u8,
+    pub motor_type:  MotorType,
+    pub position:    f64,
+    pub velocity:    f64,
+    pub current:     f64, // 0 for the M2006, which doesn't report it
+    pub temperature: f64, // 0 for the M2006, which doesn't report it
+    pub multi_turn:  f64,
+    pub received:    Instant,
+}
+
+impl FeedbackUpdate {
+    fn new(id: u8, motor: &Motor) -> FeedbackUpdate {
+        let reported = |field: FbField| if motor.motor_type == MotorType::M2006 { 0.0 } else { motor.state(field) };
+        FeedbackUpdate {
+            id,
+            motor_type:  motor.motor_type,
+            position:    motor.state(FbField::Position),
+            velocity:    motor.state(FbField::Velocity),
+            current:     reported(FbField::Current),
+            temperature: reported(FbField::Temperature),
+            multi_turn:  motor.state(FbField::MultiTurn),
+            received:    instant(motor.received.unwrap_or_default()),
+        }
+    }
+}
+
+enum Bus {
+    Socket(CanSocket),
+    // Any other CanTransport e.g. the simulator. Its recv_timeout blocks, so a thread receives from it.
+    Transport(Arc<dyn CanTransport>, tokio::sync::Mutex<mpsc::UnboundedReceiver<io::Result<(CanFrame, FrameTimestamps)>>>),
+}
+
+impl Bus {
+    fn from_transport(transport: Arc<dyn CanTransport>) -> Result<Bus, RmError> {
+        let (tx, rx) = mpsc::unbounded_channel::<io::Result<(CanFrame, FrameTimestamps)>>();
+        let receiver: Arc<dyn CanTransport> = transport.clone();
+        // Exits once the Bus is dropped
+        thread::Builder::new().name("rm_motors_can_rx".to_string()).spawn(move || while !tx.is_closed() {
+            match receiver.recv_timestamped(RX_TIMEOUT) {
+                Ok(None) => (),
+                Ok(Some(received)) => {let _ = tx.send(Ok(received));},
+                Err(err) => {
+                    let _ = tx.send(Err(err));
+                    thread::sleep(RX_TIMEOUT);
+                },
+            }
+        })?;
+        Ok(Bus::Transport(transport, tokio::sync::Mutex::new(rx)))
+    }
+
+    async fn send(&self, frame: CanFrame) -> io::Result<()> {
+        match self {
+            Bus::Socket(socket) => socket.write_frame(frame).await,
+            // Sending on the simulator or a loopback doesn't block
+            Bus::Transport(transport, _) => transport.send(&frame),
+        }
+    }
+
+    async fn recv(&self) -> io::Result<(CanFrame, FrameTimestamps)> {
+        match self {
+            Bus::Socket(socket) => socket.read_frame_with_timestamps().await.map(|(frame, ts)| (frame, ts.into())),
+            Bus::Transport(_, rx) => rx.lock().await.recv().await.unwrap_or_else(|| Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn set_filters(&self, filters: &[CanFilter]) -> io::Result<()> {
+        match self {
+            Bus::Socket(socket) => socket.set_filters(filters),
+            Bus::Transport(transport, _) => transport.set_filters(filters),
+        }
+    }
+}
+
+/*
+**  Async handle to a bus for tokio applications, sharing its motors, controllers and protocol handling with RmMotorsCan
+**  The methods of RmMotorsCan that don't block (init_motor, set_cmd, get_state, the gains, move_to...) are available through Deref.
+**  run_once and cleanup are replaced by async versions that send on an async socket.
+**  Feedback is received by a task on the runtime, which ends along with the loop when the handle is dropped.
+*/
+pub struct AsyncRmMotorsCan {
+    rm_motors_can: Arc<RmMotorsCan>,
+    bus:           Arc<Bus>,
+    updates:       broadcast::Sender<FeedbackUpdate>,
+    rx_task:       JoinHandle<()>,
+    loop_task:     std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AsyncRmMotorsCan {
+    /*
+    **  Same as RmMotorsCan::open, without blocking the runtime
+    **  interface: SocketCAN interface name e.g. "can0", or "sim:TYPE:ID,TYPE:ID,..." for simulated motors
+    */
+    pub async fn open(interface: &str) -> Result<AsyncRmMotorsCan, RmError> {
+        let bus: Bus = match sim_spec(interface) {
+            Some(spec) => Bus::from_transport(Arc::new(SimBus::from_spec(spec)?))?,
+            None => {
+                let socket: CanSocket = CanSocket::open(interface).map_err(|err| RmError::InvalidInterface(format!("{}: {}", interface, err)))?;
+                // Same as BusBuilder's default
+                enable_timestamps(&socket, false)?;
+                Bus::Socket(socket)
+            },
+        };
+        AsyncRmMotorsCan::start(bus).await
+    }
+
+    // Same as open, but over any CanTransport. Its send shouldn't block, since it is called from the runtime.
+    pub async fn with_transport(transport: Box<dyn CanTransport>) -> Result<AsyncRmMotorsCan, RmError> {
+        AsyncRmMotorsCan::start(Bus::from_transport(Arc::from(transport))?).await
+    }
+
+    async fn start(bus: Bus) -> Result<AsyncRmMotorsCan, RmError> {
+        // Listen to check if a CAN bus driver is already running- don't want to send conflicting commands.
+        let deadline: time::Instant = time::Instant::now() + LISTEN_TIME;
+        while let Ok(received) = time::timeout_at(deadline, bus.recv()).await {
+            if let CanFrame::Data(frame) = received?.0 {
+                if is_command_id(frame.raw_id() as u16) {
+                    return Err(RmError::ConflictingController);
+                }
+            }
+        }
+        bus.set_filters(&[feedback_filter()])?;
+
+        let rm_motors_can: Arc<RmMotorsCan> = Arc::new(RmMotorsCan::default());
+        let bus: Arc<Bus> = Arc::new(bus);
+        let (updates, _) = broadcast::channel(UPDATES);
+        let rx_task: JoinHandle<()> = tokio::spawn(receive(rm_motors_can.clone(), bus.clone(), updates.clone()));
+        // Give the receive task time to populate feedbacks - this prevents run_once from thinking motors aren't initialized
+        time::sleep(Duration::from_millis(5)).await;
+        Ok(AsyncRmMotorsCan { rm_motors_can, bus, updates, rx_task, loop_task: Default::default() })
+    }
+
+    // Feedback of every motor as it arrives
+    pub fn feedback(&self) -> impl Stream<Item = FeedbackUpdate> {
+        BroadcastStream::new(self.updates.subscribe()).filter_map(|update| update.ok())
+    }
+
+    // Same as RmMotorsCan::run_once, sending the commands on the async socket
+    pub async fn run_once(&self) -> Result<(), RmError> {
+        run_once(&self.rm_motors_can, &self.bus).await
+    }
+
+    /*
+    **  Spawn a task that calls run_once every period_us microseconds, replacing any loop already running
+    **  It stops with stop_loop, cleanup, or when the handle is dropped. Only the first of a run of errors is printed.
+    */
+    pub fn start_loop(&self, period_us: u64) -> Result<(), RmError> {
+        if period_us == 0 {
+            return Err(RmError::InvalidParameter { name: "period_us", value: 0.0 });
+        }
+        let (rm_motors_can, bus): (Arc<RmMotorsCan>, Arc<Bus>) = (self.rm_motors_can.clone(), self.bus.clone());
+        let task: JoinHandle<()> = tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_micros(period_us));
+            // Don't try to catch up with a burst of iterations
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut failing: bool = false;
+            loop {
+                interval.tick().await;
+                match run_once(&rm_motors_can, &bus).await {
+                    Err(e) => {
+                        if !failing { eprintln!("{}", e); }
+                        failing = true;
+                    },
+                    Ok(()) => failing = false,
+                }
+            }
+        });
+        if let Some(previous) = lock(&self.loop_task).replace(task) {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    pub fn stop_loop(&self) {
+        if let Some(task) = lock(&self.loop_task).take() {
+            task.abort();
+        }
+    }
+
+    /*
+    **  Same as RmMotorsCan::estop, sending on the async socket. Call RmMotorsCan::faulted and clear_fault through Deref.
+    **  The bus is latched when the future is first polled, so if it is dropped before sending, the next run_once still stops the motors.
+    */
+    pub async fn estop(&self) -> Result<(), RmError> {
+        self.rm_motors_can.latch_fault();
+        let mut r: Result<(), RmError> = Ok(());
+        for frame in CMD_IDS.into_iter().filter_map(zero_frame) {
+            r = r.and(self.bus.send(frame).await.map_err(RmError::from));
+        }
+        r
+    }
+
+    /*
+    **  Same as RmMotorsCan::cleanup without blocking the runtime: stop the loop, then ramp the motors' commands down to 0
+    **  period_ms: time between steps of the ramp, 0 to stop at once
+    */
+    pub async fn cleanup(&self, period_ms: u64) -> Result<(), RmError> {
+        self.stop_loop();
+        // Step all the motors down together
+        let mut ramps: Vec<(usize, f64)> = (0 .. ARR_LEN)
+            .filter(|&i| self.motor(i).mode != CmdMode::Disabled)
+            .filter_map(|i| Some((i, self.begin_ramp_down(i)?)))
+            .collect();
+        loop {
+            for (i, cmd) in ramps.iter_mut() {
+                *cmd = if cmd.abs() <= RAMP_STEP || period_ms == 0 { 0.0 } else { *cmd - cmd.signum()*RAMP_STEP };
+                let (id, cmd): (u8, f64) = (motor::id(*i), *cmd);
+                self.update_motor(*i, |motor| motor.set_cmd(id, cmd)).map_or_else(|e| eprintln!("{}", e), |_| ());
+            }
+            self.run_once().await.map_or_else(|e| eprintln!("{}", e), |_| ());
+            if ramps.iter().all(|(_, cmd)| *cmd == 0.0) {break;}
+            time::sleep(Duration::from_millis(period_ms)).await;
+        }
+        Ok(())
+    }
+}
+
+impl Deref for AsyncRmMotorsCan {
+    type Target = RmMotorsCan;
+    fn deref(&self) -> &RmMotorsCan {
+        &self.rm_motors_can
+    }
+}
+
+impl Drop for AsyncRmMotorsCan {
+    fn drop(&mut self) {
+        self.stop_loop();
+        self.rx_task.abort();
+    }
+}
+
+async fn run_once(rm_motors_can: &RmMotorsCan, bus: &Bus) -> Result<(), RmError> {
+    let (frames, r_controllers, estops): Commands = rm_motors_can.prepare_commands()?;
+    // Send the commands, stopping at the first error
+    for frame in frames {
+        let sent: CanFrame = rm_motors_can.unless_faulted(&frame, estops);
+        bus.send(sent).await.inspect_err(|_| rm_motors_can.record_all(Fault::BusError))?;
+        if let Some(zeros) = rm_motors_can.zeros_after(&sent, estops) {
+            bus.send(zeros).await.inspect_err(|_| rm_motors_can.record_all(Fault::BusError))?;
+        }
+    }
+    r_controllers
+}
+
+// Update the state from received feedback, and publish it to the feedback streams
+async fn receive(rm_motors_can: Arc<RmMotorsCan>, bus: Arc<Bus>, updates: broadcast::Sender<FeedbackUpdate>) {
+    loop {
+        match bus.recv().await {
+            Ok((frame, timestamps)) => if let Some(idx) = rm_motors_can.rx_fb(&frame, &timestamps) {
+                // No subscribers isn't an error
+                let _ = updates.send(FeedbackUpdate::new(motor::id(idx), &rm_motors_can.motor(idx)));
+            },
+            Err(err) => {
+                // Reported by the next run_once. Back off so a persistent error doesn't spin.
+                *lock(&rm_motors_can.rx_error) = Some(err);
+                time::sleep(RX_TIMEOUT).await;
+            },
+        }
+    }
+}
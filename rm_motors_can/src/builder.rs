@@ -0,0 +1,156 @@
+use crate::bus::feedback_filter;
+use crate::protocol::is_command_id;
+use crate::transport::enable_timestamps;
+use crate::{gear_ratio, CanTransport, CmdMode, MotorType, RmError, RmMotorsCan, SimBus};
+use socketcan::{CanFrame, CanSocket, Frame, Socket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub(crate) const LISTEN_TIME: Duration = Duration::from_millis(100);
+
+enum Bus {
+    Interface(String),
+    Transport(Box<dyn CanTransport>),
+}
+
+/*
+**  Configuration of a bus, from RmMotorsCan::builder
+**  e.g. RmMotorsCan::builder().interface("can0").motor(1, MotorType::GM6020, CmdMode::Voltage).start_loop(1000).build()
+*/
+pub struct BusBuilder {
+    bus:       Option<Bus>,
+    listen:    Duration,
+    motors:    Vec<(u8, MotorType, CmdMode, f64)>, // id, type, mode, gear ratio
+    period_us: Option<u64>,                         // start_loop after initializing the motors
+    kernel_ts: bool,
+    hw_ts:     bool,
+}
+
+impl BusBuilder {
+    /*
+    **  interface: SocketCAN interface name e.g. "can0", or "sim:TYPE:ID,TYPE:ID,..." for simulated motors e.g. "sim:GM6020:1,M3508:2"
+    */
+    pub fn interface(mut self, interface: &str) -> BusBuilder {
+        self.bus = Some(Bus::Interface(interface.to_string()));
+        self
+    }
+
+    // Communicate over any CanTransport rather than opening an interface e.g. a LoopbackTransport for testing without hardware
+    pub fn transport(mut self, transport: Box<dyn CanTransport>) -> BusBuilder {
+        self.bus = Some(Bus::Transport(transport));
+        self
+    }
+
+    // How long to listen for commands from another controller before taking over the bus. 0 skips the check.
+    pub fn listen_time(mut self, listen: Duration) -> BusBuilder {
+        self.listen = listen;
+        self
+    }
+
+    /*
+    **  Timestamp feedback when the kernel receives it rather than when the receive thread gets to it, see RmMotorsCan::feedback_time
+    **  On by default. Only SocketCAN interfaces have kernel timestamps, other transports are always timestamped in userspace.
+    */
+    pub fn kernel_timestamps(mut self, enable: bool) -> BusBuilder {
+        self.kernel_ts = enable;
+        self
+    }
+
+    // Also ask the CAN adapter for its own timestamps, if it supports them. Implies kernel_timestamps.
+    pub fn hardware_timestamps(mut self, enable: bool) -> BusBuilder {
+        self.hw_ts = enable;
+        self
+    }
+
+    // init_motor once the bus is up
+    pub fn motor(self, id: u8, motor_type: MotorType, mode: CmdMode) -> BusBuilder {
+        self.motor_geared(id, motor_type, mode, gear_ratio(motor_type))
+    }
+
+    // init_motor_geared once the bus is up
+    pub fn motor_geared(mut self, id: u8, motor_type: MotorType, mode: CmdMode, gear_ratio: f64) -> BusBuilder {
+        self.motors.push((id, motor_type, mode, gear_ratio));
+        self
+    }
+
+    // start_loop once the motors are initialized
+    pub fn start_loop(mut self, period_us: u64) -> BusBuilder {
+        self.period_us = Some(period_us);
+        self
+    }
+
+    pub fn build(self) -> Result<Arc<RmMotorsCan>, RmError> {
+        let transport: Box<dyn CanTransport> = match self.bus {
+            Some(Bus::Transport(transport)) => transport,
+            Some(Bus::Interface(interface)) => open(&interface, self.kernel_ts || self.hw_ts, self.hw_ts)?,
+            None => return Err(RmError::InvalidInterface("No interface or transport given".to_string())),
+        };
+        let rm_motors_can: Arc<RmMotorsCan> = Arc::new(RmMotorsCan::default());                      // Arc (Atomically Reference Counted) is like shared_ptr in C++
+
+        // Listen to check if a CAN bus driver is already running- don't want to send conflicting commands.
+        let t: Instant = Instant::now();
+        while t.elapsed() < self.listen {
+            match transport.recv_timeout(self.listen - t.elapsed())? {
+                None => (),
+                Some(CanFrame::Remote(_)) => (),
+                Some(CanFrame::Error(_)) => (),
+                Some(CanFrame::Data(frame)) if is_command_id(frame.raw_id() as u16) => return Err(RmError::ConflictingController),
+                Some(CanFrame::Data(_)) => (),
+            };
+        }
+
+        transport.set_filters(&[feedback_filter()])?;                                 // Apply the filter to our interface
+        let transport: Arc<dyn CanTransport> = Arc::from(transport);
+        let _ = rm_motors_can.transport.set(transport.clone());            // Attach the transport to the rm_motors_can object for future writing. It was created empty above.
+        rm_motors_can.spawn_rx(transport)?;
+
+        // Give the receive thread time to populate feedbacks - this prevents run_once from thinking motors aren't initialized
+        thread::sleep(Duration::from_millis(5));
+
+        for (id, motor_type, mode, gear_ratio) in self.motors {
+            rm_motors_can.init_motor_geared(id, motor_type, mode, gear_ratio)?;
+        }
+        if let Some(period_us) = self.period_us {
+            rm_motors_can.start_loop(period_us)?;
+        }
+        Ok(rm_motors_can)
+    }
+}
+
+fn open(interface: &str, kernel_ts: bool, hw_ts: bool) -> Result<Box<dyn CanTransport>, RmError> {
+    if let Some(spec) = sim_spec(interface) {
+        return Ok(Box::new(SimBus::from_spec(spec)?));
+    }
+    let socket: CanSocket = CanSocket::open(interface).map_err(|err| RmError::InvalidInterface(format!("{}: {}", interface, err)))?;  // Attempt to open the given interface
+    if kernel_ts {
+        enable_timestamps(&socket, hw_ts)?;
+    }
+    Ok(Box::new(socket))
+}
+
+// The motors of a "sim:TYPE:ID,..." interface name, None for any other interface
+pub(crate) fn sim_spec(interface: &str) -> Option<&str> {
+    if interface == "sim" || interface.starts_with("sim:") {
+        return Some(interface.strip_prefix("sim").unwrap_or_default().trim_start_matches(':'));
+    }
+    None
+}
+
+impl RmMotorsCan {
+    pub fn builder() -> BusBuilder {
+        BusBuilder { bus: None, listen: LISTEN_TIME, motors: Vec::new(), period_us: None, kernel_ts: true, hw_ts: false }
+    }
+
+    /*
+    **  Open a bus with the default configuration, see BusBuilder::interface
+    */
+    pub fn open(interface: &str) -> Result<Arc<RmMotorsCan>, RmError> {
+        RmMotorsCan::builder().interface(interface).build()
+    }
+
+    // Same as open, but over any CanTransport, see BusBuilder::transport
+    pub fn with_transport(transport: Box<dyn CanTransport>) -> Result<Arc<RmMotorsCan>, RmError> {
+        RmMotorsCan::builder().transport(transport).build()
+    }
+}
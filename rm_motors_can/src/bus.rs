@@ -0,0 +1,718 @@
+use crate::motor::{self, CommandGroups, Motor, Notice};
+use crate::protocol::{self, Feedback, FB_ID_BASE_3508};
+use crate::{gear_ratio, CanTransport, CmdMode, Fault, FaultRecord, FbField, FrameTimestamps, IoLoop, LinkPolicy, LinkStatus, MotorId, MotorType, MotorStatus, MoveStatus, PidGains, RmError, Stage, StageSignal, TimestampSource};
+use crate::{ARR_LEN, RAMP_STEP, RX_TIMEOUT};
+use arc_swap::{ArcSwap, Guard};
+use embedded_can::Frame as EmbeddedFrame;
+use socketcan::{CanFilter, CanFrame};
+use std::io;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// A kernel timestamp further than this from when the frame was read means the wall clock stepped in between, so it isn't used
+const MAX_KERNEL_DELAY: Duration = Duration::from_secs(1);
+
+// States of RmMotorsCan::fault. Clearing is still faulted, so an estop during clear_fault isn't lost.
+const RUNNING:  u8 = 0;
+const FAULTED:  u8 = 1;
+const CLEARING: u8 = 2;
+
+// One motor's state as of a snapshot, in the units of get_state
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub struct MotorSnapshot {
+    pub bus:             usize, // Index of the bus in a BusManager, 0 from RmMotorsCan::snapshot
+    pub motor_type:      MotorType,
+    pub id:              u8,
+    pub mode:            CmdMode,
+    pub position:        f64,
+    pub velocity:        f64,
+    pub current:         f64, // 0 for the M2006, which doesn't report it
+    pub temperature:     f64, // 0 for the M2006, which doesn't report it
+    pub multi_turn:      f64,
+    pub feedback_age_ms: f64, // Since the latest feedback, negative if there hasn't been any
+    pub link:            LinkStatus,
+    pub status:          MotorStatus,
+}
+
+/*
+**  When a motor's latest feedback was received, for differentiating or fusing it with other sensors
+**  received is on the monotonic clock (CLOCK_MONOTONIC on Linux, the clock of std::chrono::steady_clock),
+**  so it doesn't jump when the wall clock is set.
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FeedbackTime {
+    pub received: Instant,
+    pub age:      Duration,         // Since received, as of the call
+    pub source:   TimestampSource,  // Kernel if the socket timestamped the frame, see BusBuilder::kernel_timestamps
+    pub hardware: Option<Duration>, // The CAN adapter's timestamp, see BusBuilder::hardware_timestamps. Its clock isn't comparable to received.
+}
+
+impl FeedbackTime {
+    // received as a time since boot on CLOCK_MONOTONIC, for code outside Rust that doesn't have an Instant to compare to
+    pub fn monotonic(&self) -> Duration {
+        monotonic(self.received)
+    }
+}
+
+// An Instant as a time since boot on CLOCK_MONOTONIC
+fn monotonic(t: Instant) -> Duration {
+    let mut ts: libc::timespec = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    let clock_now: Duration = Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+    clock_now.saturating_sub(t.elapsed())
+}
+
+#[repr(C)]
+pub struct RmMotorsCan {
+    pub(crate) transport : OnceLock<Arc<dyn CanTransport>>, // Set by init_bus, shared with the receive thread
+    pub(crate) rx_error  : Mutex<Option<io::Error>>,         // Latest error from the receive thread, reported by run_once
+    motors               : [ArcSwap<Motor>; ARR_LEN],        // Indexed by family and ID, see motor::index
+    fault                : AtomicU8,                         // Latched by estop, see RUNNING
+    estops               : AtomicU32,                        // Counts estops, so frames built before one are never sent, see unless_faulted
+    pub(crate) io_loop   : IoLoop,
+}
+
+impl Default for RmMotorsCan {
+    fn default() -> Self {
+        RmMotorsCan {
+            transport: OnceLock::new(),
+            rx_error:  Mutex::new(None),
+            motors:    std::array::from_fn(|i| ArcSwap::from_pointee(motor::blank(i))),
+            fault:     AtomicU8::new(RUNNING),
+            estops:    AtomicU32::new(0),
+            io_loop:   IoLoop::default(),
+        }
+    }
+}
+
+// The time the motors' state is kept in: since the first call, on the monotonic clock
+pub(crate) fn now() -> Duration {
+    epoch().elapsed()
+}
+
+// Convert a time from now() back to an Instant
+pub(crate) fn instant(t: Duration) -> Instant {
+    epoch() + t
+}
+
+// Far enough in the past that any kernel timestamp received_at accepts comes after it
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(|| Instant::now().checked_sub(MAX_KERNEL_DELAY).unwrap_or_else(Instant::now))
+}
+
+/*
+**  When a frame that was just read was received, as a time from now()
+**  The kernel stamps frames on the wall clock, so its timestamp is converted by how long ago it was rather than used as is.
+*/
+pub(crate) fn received_at(timestamps: &FrameTimestamps) -> (Duration, TimestampSource) {
+    let now: Duration = now();
+    match timestamps.kernel.map(|kernel| SystemTime::now().duration_since(kernel)) {
+        Some(Ok(delay)) if delay <= MAX_KERNEL_DELAY => (now.saturating_sub(delay), TimestampSource::Kernel),
+        _ => (now, TimestampSource::Userspace),
+    }
+}
+
+// Only accept messages with IDs from 0x200 to 0x20F (Motor feedbacks are 0x201 to 0x20B)
+pub(crate) fn feedback_filter() -> CanFilter {
+    CanFilter::new(FB_ID_BASE_3508 as u32, 0xffff - 0xf)
+}
+
+/*
+**  Build a CAN frame with motor commands
+**
+**  frame_id: which motor type, command and ID range the frame is for
+**  commands: one for each motor in the ID range
+*/
+fn cmd_frame(frame_id: u16, commands: [i16; 4]) -> Result<CanFrame, RmError> {
+    protocol::command_frame(frame_id, commands)
+        .ok_or_else(|| RmError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Failed to build command frame {:#x}", frame_id))))
+}
+
+// A command frame of all zeros, built without allocating
+pub(crate) fn zero_frame(frame_id: u16) -> Option<CanFrame> {
+    protocol::command_frame(frame_id, [0; 4])
+}
+
+// The command frames of one run_once, the result of the controllers that produced them, and the number of estops before them
+pub(crate) type Commands = (Vec<CanFrame>, Result<(), RmError>, u32);
+
+// A panic in another thread while holding one of these locks doesn't leave the plain data inside in a bad state,
+// so carry on with it rather than spreading the panic to the control thread.
+pub(crate) fn lock<T: ?Sized>(m: &Mutex<T>) -> MutexGuard<'_, T> { m.lock().unwrap_or_else(PoisonError::into_inner) }
+
+impl RmMotorsCan {
+    // Snapshot of one motor's state
+    pub(crate) fn motor(&self, idx: usize) -> Guard<Arc<Motor>> {
+        self.motors[idx].load()
+    }
+
+    // Table index and ID of a motor, see MotorId
+    fn resolve(&self, id: impl Into<MotorId>) -> Result<(usize, u8), RmError> {
+        motor::resolve(id.into(), |i| self.motor(i).mode)
+    }
+
+    /*
+    **  Apply f to a copy of one motor's state and publish the result, returning what f returns
+    **  If another thread published in the meantime f is run again on its state, so f shouldn't have side effects.
+    **  Anything f has to warn about is left in Motor::notice, and printed once the result is published.
+    **  Copies the whole Motor (about 1.2kB) into a new allocation each time: about 0.5us, see benches/state_contention.rs.
+    */
+    pub(crate) fn update_motor<R>(&self, idx: usize, mut f: impl FnMut(&mut Motor) -> R) -> R {
+        let mut current: Guard<Arc<Motor>> = self.motors[idx].load();
+        loop {
+            let mut motor: Motor = Motor::clone(&current);
+            let r: R = f(&mut motor);
+            let notice: Option<Notice> = motor.notice.take();
+            let previous: Guard<Arc<Motor>> = self.motors[idx].compare_and_swap(&*current, Arc::new(motor));
+            if Arc::ptr_eq(&previous, &current) {
+                // Only once it is stored, since f runs again if another thread got there first
+                if let Some(notice) = notice {
+                    eprintln!("{}", notice);
+                }
+                return r;
+            }
+            current = previous;
+        }
+    }
+
+    pub fn init_motor(&self, id: u8, motor_type: MotorType, mode: CmdMode) -> Result<(), RmError> {
+        self.init_motor_geared(id, motor_type, mode, gear_ratio(motor_type))
+    }
+
+    /*
+    **  Same as init_motor, but with a gear ratio other than the datasheet's e.g. an M3508 with a different gearbox, or an external reduction
+    **  gear_ratio: rotor turns per output shaft turn. Velocity and MultiTurn feedback, and Torque and Velocity commands, are all at the output shaft.
+    */
+    pub fn init_motor_geared(&self, id: u8, motor_type: MotorType, mode: CmdMode, gear_ratio: f64) -> Result<(), RmError> {
+        let idx: usize = motor::check_init(id, motor_type, mode, gear_ratio, |i| {
+            let other: Guard<Arc<Motor>> = self.motor(i);
+            (other.motor_type, other.mode)
+        })?;
+
+        let actual: Guard<Arc<Motor>> = self.motor(idx);
+        if actual.mode == CmdMode::Disabled {
+            println!("Initializing {}:{} in {} mode", motor_type, id, mode);
+        }
+        // A motor's mode shouldn't change at runtime because it requires setting a parameter in RoboMaster Assistant
+        else{
+            if actual.mode != mode {
+                eprintln!("Warning: Changing {}:{} from {} to {} mode", motor_type, id, actual.mode, mode);
+            }
+            if actual.motor_type != motor_type {
+                eprintln!("Warning: Changing motor {} from {} to {} type", id, actual.motor_type, motor_type);
+            }
+        }
+        drop(actual);
+        self.update_motor(idx, |motor| motor.init(motor_type, mode, gear_ratio));
+        Ok(())
+    }
+
+    /*
+    **  Ramp the motors' commands down to 0, then stop the I/O loop
+    **  period_ms: time between steps of the ramp, 0 to stop at once
+    */
+    pub fn cleanup(&self, period_ms: u64) -> Result<(), RmError> {
+        // Ramp down commands to avoid jerking stop
+        thread::scope(|scope| {
+            let mut threads: Vec<thread::ScopedJoinHandle<()>> = Vec::new();
+            for i in 0 .. ARR_LEN {
+                if self.motor(i).mode == CmdMode::Disabled {continue;}
+                // Multi-thread so all motors spin down at once
+                threads.push(scope.spawn( move ||{
+                    let mut cmd: f64 = match self.begin_ramp_down(i) {
+                        Some(cmd) => cmd,
+                        None => return, // Checked before spawning
+                    };
+                    let sign: f64 = cmd/cmd.abs();
+                    let id: u8 = motor::id(i);
+                    loop{
+                        if cmd.abs() <= RAMP_STEP || period_ms == 0 {
+                            self.update_motor(i, |motor| motor.set_cmd(id, 0f64)).map_or_else(|e| eprintln!("{}", e), |_| ());
+                            self.run_once().map_or_else(|e| eprintln!("{}", e), |_| ());
+                            break;
+                        }
+                        cmd -= sign*RAMP_STEP;
+                        self.update_motor(i, |motor| motor.set_cmd(id, cmd)).map_or_else(|e| eprintln!("{}", e), |_| ());
+                        self.run_once().map_or_else(|e| eprintln!("{}", e), |_| ());
+                        thread::sleep(std::time::Duration::from_millis(period_ms));
+                    }
+                }));
+            }
+            for thread in threads.into_iter() {
+                if thread.join().is_err() {
+                    eprintln!("A cleanup thread panicked");
+                }
+            }
+        });
+        self.stop_loop()?;
+        self.join_loop()
+    }
+
+    // See Motor::begin_ramp_down
+    pub(crate) fn begin_ramp_down(&self, idx: usize) -> Option<f64> {
+        self.update_motor(idx, Motor::begin_ramp_down)
+    }
+
+    /*
+    **  Emergency stop: latch the bus faulted and send a 0 command in every command frame at once, without waiting for run_once
+    **  While faulted run_once commands every motor 0, and set_cmd and move_to return EStopped, until clear_fault.
+    **  Takes no locks and doesn't allocate, so it can be called from another thread or a signal handler,
+    **  as long as the transport's send doesn't either (a SocketCAN interface only writes to its socket).
+    **  The bus is latched even if sending fails.
+    */
+    pub fn estop(&self) -> Result<(), RmError> {
+        self.latch_fault();
+        let transport: &Arc<dyn CanTransport> = self.transport.get().ok_or(RmError::NotInitialized)?;
+        let mut r: Result<(), RmError> = Ok(());
+        // Keep going after a failed frame so it doesn't leave the rest running
+        for frame in protocol::CMD_IDS.into_iter().filter_map(zero_frame) {
+            r = r.and(transport.send(&frame).map_err(RmError::from));
+        }
+        r
+    }
+
+    pub(crate) fn latch_fault(&self) {
+        self.estops.fetch_add(1, Ordering::SeqCst);
+        self.fault.store(FAULTED, Ordering::SeqCst);
+    }
+
+    // Whether the bus is latched by estop
+    pub fn faulted(&self) -> Result<bool, RmError> {
+        Ok(self.is_faulted())
+    }
+
+    pub(crate) fn is_faulted(&self) -> bool {
+        self.fault.load(Ordering::SeqCst) != RUNNING
+    }
+
+    /*
+    **  Resume after estop. Every motor holds its position (Position mode) or stays stopped until the next set_cmd.
+    **  Returns EStopped if estop was called again while clearing, in which case the bus stays latched.
+    */
+    pub fn clear_fault(&self) -> Result<(), RmError> {
+        if self.fault.compare_exchange(FAULTED, CLEARING, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return Ok(());
+        }
+        // Commands set before the estop aren't resumed
+        self.stop_all();
+        self.fault.compare_exchange(CLEARING, RUNNING, Ordering::SeqCst, Ordering::SeqCst).map(|_| ()).map_err(|_| RmError::EStopped)
+    }
+
+    // Record a fault on every enabled motor, for a problem with the bus rather than one of them
+    pub(crate) fn record_all(&self, fault: Fault) {
+        let now: Duration = now();
+        for i in 0 .. ARR_LEN {
+            if self.motor(i).mode == CmdMode::Disabled {continue;}
+            self.update_motor(i, |motor| {
+                motor.tick(now);
+                motor.record(fault, 0.0);
+            });
+        }
+    }
+
+    // Motor::stop every enabled motor
+    fn stop_all(&self) {
+        for i in 0 .. ARR_LEN {
+            if self.motor(i).mode == CmdMode::Disabled {continue;}
+            self.update_motor(i, Motor::stop);
+        }
+    }
+
+    /*
+    **  The frame to send in place of one from prepare_commands: all zeros if estop was called since it was built, even if clear_fault was too
+    **  estops: as returned by prepare_commands with the frame
+    */
+    pub(crate) fn unless_faulted(&self, frame: &CanFrame, estops: u32) -> CanFrame {
+        match protocol::data_frame_id(frame).filter(|_| self.estopped_since(estops)).and_then(zero_frame) {
+            Some(zeros) => zeros,
+            None => *frame,
+        }
+    }
+
+    /*
+    **  The zeros to follow a frame from unless_faulted with, if estop was called while it was being sent
+    **  estop may have sent its own zeros between the check and the send, and they mustn't be the ones overtaken.
+    */
+    pub(crate) fn zeros_after(&self, sent: &CanFrame, estops: u32) -> Option<CanFrame> {
+        protocol::data_frame_id(sent).filter(|_| self.estopped_since(estops)).and_then(zero_frame).filter(|zeros| zeros.data() != sent.data())
+    }
+
+    fn estopped_since(&self, estops: u32) -> bool {
+        self.is_faulted() || self.estops.load(Ordering::SeqCst) != estops
+    }
+
+    pub fn run_once(&self) -> Result<(), RmError>{
+        let (frames, r_controllers, estops): Commands = self.prepare_commands()?;
+        self.send_commands(&frames, estops).and(r_controllers)
+    }
+
+    // Send the frames from prepare_commands, stopping at the first error
+    pub(crate) fn send_commands(&self, frames: &[CanFrame], estops: u32) -> Result<(), RmError> {
+        let transport: &Arc<dyn CanTransport> = self.transport.get().ok_or(RmError::NotInitialized)?;
+        for frame in frames {
+            let sent: CanFrame = self.unless_faulted(frame, estops);
+            transport.send(&sent).inspect_err(|_| self.record_all(Fault::BusError))?;
+            if let Some(zeros) = self.zeros_after(&sent, estops) {
+                transport.send(&zeros).inspect_err(|_| self.record_all(Fault::BusError))?;
+            }
+        }
+        Ok(())
+    }
+
+    /*
+    **  Everything run_once does before sending: check the feedback, run the controllers and build the command frames
+    **  Also returns the result of the controllers, since an error from one motor's controller shouldn't stop commands going to the rest
+    **  While faulted every motor is commanded 0, whatever its feedback.
+    */
+    pub(crate) fn prepare_commands(&self) -> Result<Commands, RmError> {
+        let estops: u32 = self.estops.load(Ordering::SeqCst);
+        let r_controllers: Result<(), RmError> = if self.is_faulted() {
+            self.record_all(Fault::EStop);
+            self.stop_all();
+            Ok(())
+        } else {
+            self.check_fb()?;
+            self.run_controllers()
+        };
+        let groups: CommandGroups = CommandGroups::new(|i| {
+            let motor: Guard<Arc<Motor>> = self.motor(i);
+            (motor.motor_type, motor.mode, motor.sent_command())
+        });
+        let frames: Vec<CanFrame> = groups.iter().map(|(frame_id, commands)| cmd_frame(*frame_id, *commands)).collect::<Result<_, _>>()?;
+        Ok((frames, r_controllers, estops))
+    }
+
+    // Stop motors whose link is lost or whose command has timed out, and update the commands of motors in closed-loop modes from the latest feedback
+    fn run_controllers(&self) -> Result<(), RmError> {
+        let now: Duration = now();
+        let mut r: Result<(), RmError> = Ok(());
+        for i in 0 .. ARR_LEN {
+            if self.motor(i).mode == CmdMode::Disabled {continue;}
+            r = r.and(self.update_motor(i, |motor| {
+                let r_link: Result<(), RmError> = motor.run_link(motor::id(i), now);
+                r_link.and(motor.run_watchdog(motor::id(i), now)).and(motor.run_thermal(motor::id(i)))
+            }));
+        }
+        if (0 .. ARR_LEN).any(|i| self.motor(i).faults_bus()) {
+            self.stop_all();
+            return r;
+        }
+        for i in 0 .. ARR_LEN {
+            // No feedback yet is reported by check_fb
+            if !self.motor(i).needs_controller() {continue;}
+            r = r.and(self.update_motor(i, |motor| motor.run_controller(motor::id(i), now)));
+        }
+        r
+    }
+
+
+
+    /*
+    **  Command a motor in the units of its mode (see README)
+    **  In Position and Velocity modes this sets the target, which run_once drives towards
+    */
+    pub fn set_cmd(&self, id: impl Into<MotorId>, cmd: f64) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        if self.is_faulted() {
+            return Err(RmError::EStopped);
+        }
+        self.update_motor(idx, |motor| motor.set_cmd(id, cmd))
+    }
+
+    /*
+    **  Stop a motor if the application stops commanding it e.g. its control thread hangs, rather than run_once re-sending the last command forever
+    **  If neither set_cmd nor move_to is called for timeout_ms, run_once ramps the command to 0 over ramp_ms (0 stops it at once), holds it there
+    **  and returns CommandTimeout until the next command. timeout_ms 0 turns it off, which is the default.
+    */
+    pub fn set_command_timeout(&self, id: impl Into<MotorId>, timeout_ms: u64, ramp_ms: u64) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.update_motor(idx, |motor| motor.set_command_timeout(timeout_ms, ramp_ms));
+        Ok(())
+    }
+
+    /*
+    **  What run_once does when the motor's feedback stops for timeout_ms, see LinkPolicy. The default is Warn after 100ms.
+    **  Disable and FaultBus stay in effect after the feedback returns, until clear_link_fault.
+    */
+    pub fn set_link_policy(&self, id: impl Into<MotorId>, policy: LinkPolicy, timeout_ms: u64) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.update_motor(idx, |motor| motor.set_link_policy(policy, timeout_ms))
+    }
+
+    pub fn link_status(&self, id: impl Into<MotorId>) -> Result<LinkStatus, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motor(idx).link_status(now()))
+    }
+
+    /*
+    **  Resume a motor stopped by LinkPolicy::Disable or FaultBus, once its feedback is back
+    **  It holds its position (Position mode) or stays stopped until the next set_cmd.
+    */
+    pub fn clear_link_fault(&self, id: impl Into<MotorId>) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        let now: Duration = now();
+        self.update_motor(idx, |motor| motor.clear_link_fault(id, now))
+    }
+
+    /*
+    **  Override the temperatures (C) the motor's voltage or current limit is derated between, see TEMP_DERATE and TEMP_MAX
+    **  Above derate_c the limit falls linearly to 0 at max_c, and eases off again once the temperature has fallen by 5C.
+    */
+    pub fn set_temperature_limits(&self, id: impl Into<MotorId>, derate_c: u8, max_c: u8) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.update_motor(idx, |motor| motor.set_temperature_limits(derate_c, max_c))
+    }
+
+    // Whether the command timeout has stopped the motor, until its next command
+    pub fn command_timed_out(&self, id: impl Into<MotorId>) -> Result<bool, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motor(idx).watchdog.tripped.is_some())
+    }
+
+    /*
+    **  Set the gains of one stage of the cascade used in Position and Velocity modes (see Stage for units)
+    **  init_motor resets them to default_position_gains, default_velocity_gains and default_current_gains
+    **  On a GM6020, Current stage kp, ki or kd close a current loop, so also give the Velocity stage gains in A. Starting or stopping
+    **  that resets the Velocity stage's limit to the GM6020's current or voltage limit.
+    */
+    pub fn set_stage_gains(&self, id: impl Into<MotorId>, stage: Stage, kp: f64, ki: f64, kd: f64, kf: f64) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        self.update_motor(idx, |motor| motor.set_stage_gains(id, stage, PidGains { kp, ki, kd, kf }))
+    }
+
+    // Shorthand for set_stage_gains(Stage::Position) without feed-forward
+    pub fn set_position_gains(&self, id: impl Into<MotorId>, kp: f64, ki: f64, kd: f64) -> Result<(), RmError> {
+        self.set_stage_gains(id, Stage::Position, kp, ki, kd, 0.0)
+    }
+
+    // Shorthand for set_stage_gains(Stage::Velocity) without derivative
+    pub fn set_velocity_gains(&self, id: impl Into<MotorId>, kp: f64, ki: f64, kf: f64) -> Result<(), RmError> {
+        self.set_stage_gains(id, Stage::Velocity, kp, ki, 0.0, kf)
+    }
+
+    /*
+    **  Limit the output of one stage to [-limit, limit], e.g. the Position stage's limit is the maximum velocity
+    **  The Current stage's output is also clamped to the motor's voltage or current limit
+    */
+    pub fn set_stage_limit(&self, id: impl Into<MotorId>, stage: Stage, limit: f64) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.update_motor(idx, |motor| motor.set_stage_limit(stage, limit))
+    }
+
+    /*
+    **  Add a feed-forward input to the setpoint of one stage, e.g. the planned velocity of a trajectory or the torque current to hold up a load
+    **  It stays in effect until it is changed or the motor is re-initialized
+    */
+    pub fn set_feedforward(&self, id: impl Into<MotorId>, stage: Stage, value: f64) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.update_motor(idx, |motor| motor.set_feedforward(stage, value))
+    }
+
+    // Internal signal of one stage as of the latest run_once, for tuning. Stages the motor's mode doesn't use read 0.
+    pub fn get_stage_signal(&self, id: impl Into<MotorId>, stage: Stage, signal: StageSignal) -> Result<f64, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motor(idx).stage_signal(stage, signal))
+    }
+
+    /*
+    **  Move a motor in Position mode to target (output shaft rad) along a trapezoidal velocity profile, which run_once follows
+    **  A move in progress is retargeted from wherever its trajectory is now, without a jump in position or velocity
+    **  max_vel: rad/s, max_accel: rad/s^2
+    */
+    pub fn move_to(&self, id: impl Into<MotorId>, target: f64, max_vel: f64, max_accel: f64) -> Result<(), RmError> {
+        self.move_to_s_curve(id, target, max_vel, max_accel, 0.0)
+    }
+
+    /*
+    **  Same as move_to, with the jerk limited to max_jerk (rad/s^3) for an S-curve profile. max_jerk <= 0 is trapezoidal.
+    **  Retargeting while accelerating starts the new S-curve from zero acceleration.
+    */
+    pub fn move_to_s_curve(&self, id: impl Into<MotorId>, target: f64, max_vel: f64, max_accel: f64, max_jerk: f64) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        if self.is_faulted() {
+            return Err(RmError::EStopped);
+        }
+        let now: Duration = now();
+        self.update_motor(idx, |motor| motor.move_to(id, target, max_vel, max_accel, max_jerk, now))
+    }
+
+    pub fn move_status(&self, id: impl Into<MotorId>) -> Result<MoveStatus, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motor(idx).move_status(now()))
+    }
+
+    /*
+    **  Stop a move_to, decelerating within its limits and then holding position
+    */
+    pub fn cancel_move(&self, id: impl Into<MotorId>) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        let now: Duration = now();
+        self.update_motor(idx, |motor| motor.cancel_move(now));
+        Ok(())
+    }
+
+
+    /*
+    **  Receive feedback on a dedicated thread that blocks on the transport rather than polling it,
+    **  so feedback is always fresh regardless of how often run_once is called
+    **
+    **  The thread exits once the handle is dropped.
+    **  transport: the bus to receive from
+    */
+    pub(crate) fn spawn_rx(self: &Arc<Self>, transport: Arc<dyn CanTransport>) -> Result<(), RmError> {
+        let weak: Weak<RmMotorsCan> = Arc::downgrade(self);
+        thread::Builder::new().name("rm_motors_can_rx".to_string()).spawn(move || loop {
+            // The timeout only bounds how long the thread outlives the handle
+            let result: io::Result<Option<(CanFrame, FrameTimestamps)>> = transport.recv_timestamped(RX_TIMEOUT);
+            let rm_motors_can: Arc<RmMotorsCan> = match weak.upgrade() {
+                Some(rm_motors_can) => rm_motors_can,
+                None => return,
+            };
+            match result {
+                Ok(Some((frame, timestamps))) => {rm_motors_can.rx_fb(&frame, &timestamps);},
+                Ok(None) => (),
+                Err(err) => {
+                    // Reported by the next run_once. Back off so a persistent error doesn't spin.
+                    *lock(&rm_motors_can.rx_error) = Some(err);
+                    drop(rm_motors_can);
+                    thread::sleep(RX_TIMEOUT);
+                },
+            }
+        })?;
+        Ok(())
+    }
+
+    /*
+    **  Parse a received feedback frame
+    **
+    **  frame: the CAN frame to parse
+    **  timestamps: from the transport, if it has any
+    **  returns: the index of the motor it updated, if any
+    */
+    pub(crate) fn rx_fb(&self, frame: &CanFrame, timestamps: &FrameTimestamps) -> Option<usize> {
+        // The datasheet didn't mention any error frames but we might as well print them
+        if let CanFrame::Error(frame) = frame {
+            eprintln!("{:?}", frame);
+            return None;
+        }
+        // Not a feedback frame (transports other than SocketCAN might not apply the filter), or the filter let through an ID above the highest motor.
+        // The mask on the socket isn't a perfect match either, so it's possible we receive a remote frame for another device with a nearby id.
+        let idx: usize = motor::feedback_index(protocol::data_frame_id(frame)?, |i| self.motor(i).mode)?;
+        let feedback: Feedback = Feedback::decode(frame.data())?;
+        let (received, source): (Duration, TimestampSource) = received_at(timestamps);
+        self.update_motor(idx, |motor| {
+            motor.apply_feedback(feedback, received);
+            motor.rx_source   = source;
+            motor.rx_hardware = timestamps.hardware;
+        });
+        Some(idx)
+    }
+
+    // Report any receive error, and warn about motors that have stopped sending feedback
+    fn check_fb(&self) -> Result<(), RmError> {
+        if let Some(err) = lock(&self.rx_error).take() {
+            self.record_all(Fault::BusError);
+            return Err(RmError::Io(err));
+        }
+        // A motor that never responded stops the whole bus. Once it has, losing it is up to its link policy (see Motor::run_link).
+        let now: Duration = now();
+        for i in 0 .. ARR_LEN {
+            if self.motor(i).mode == CmdMode::Disabled {continue;}
+            self.update_motor(i, |motor| {
+                motor.begin_cycle(now);
+                motor.feedback_age(motor::id(i), now).inspect_err(|_| {motor.record(Fault::StaleFeedback, -1.0);})
+            })?;
+        }
+        Ok(())
+    }
+
+
+    /*
+    **  State of every initialized motor. Each motor's values come from the same feedback frame, but different motors' may not.
+    */
+    pub fn snapshot(&self) -> Vec<MotorSnapshot> {
+        let now: Duration = now();
+        (0 .. ARR_LEN).filter_map(|i| {
+            let motor: Guard<Arc<Motor>> = self.motor(i);
+            if motor.mode == CmdMode::Disabled {
+                return None;
+            }
+            let reported = |field: FbField| if motor.motor_type == MotorType::M2006 { 0.0 } else { motor.state(field) };
+            Some(MotorSnapshot {
+                bus:             0,
+                motor_type:      motor.motor_type,
+                id:              motor::id(i),
+                mode:            motor.mode,
+                position:        motor.state(FbField::Position),
+                velocity:        motor.state(FbField::Velocity),
+                current:         reported(FbField::Current),
+                temperature:     reported(FbField::Temperature),
+                multi_turn:      motor.state(FbField::MultiTurn),
+                feedback_age_ms: motor.received.map_or(-1.0, |received| now.saturating_sub(received).as_secs_f64()*1e3),
+                link:            motor.link_status(now),
+                status:          motor.faults.status,
+            })
+        }).collect()
+    }
+
+    /*
+    **  When the motor's latest feedback was received, and how long ago
+    **  Errors with StaleFeedback if it hasn't sent any.
+    */
+    pub fn feedback_time(&self, id: impl Into<MotorId>) -> Result<FeedbackTime, RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        let motor: Guard<Arc<Motor>> = self.motor(idx);
+        let received: Duration = motor.received.ok_or(RmError::StaleFeedback { id, age: None })?;
+        Ok(FeedbackTime {
+            received: instant(received),
+            age:      now().saturating_sub(received),
+            source:   motor.rx_source,
+            hardware: motor.rx_hardware,
+        })
+    }
+
+    /*
+    **  The faults the motor has had since clear_faults, e.g. to show why it stopped. See Fault for what is recorded.
+    */
+    pub fn motor_status(&self, id: impl Into<MotorId>) -> Result<MotorStatus, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motor(idx).faults.status)
+    }
+
+    /*
+    **  When each of the motor's faults started since clear_faults, oldest first. Only the latest 16 are kept.
+    **  The times are on CLOCK_MONOTONIC, in seconds.
+    */
+    pub fn fault_history(&self, id: impl Into<MotorId>) -> Result<Vec<FaultRecord>, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        let motor: Guard<Arc<Motor>> = self.motor(idx);
+        Ok(motor.faults.history().map(|record| FaultRecord {
+            time: monotonic(instant(Duration::from_secs_f64(record.time))).as_secs_f64(),
+            ..record
+        }).collect())
+    }
+
+    // Forget the motor's status and fault history. Doesn't resume a motor stopped by a fault, see clear_link_fault and clear_fault.
+    pub fn clear_faults(&self, id: impl Into<MotorId>) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.update_motor(idx, Motor::clear_faults);
+        Ok(())
+    }
+
+    pub fn get_state(&self, id: impl Into<MotorId>, field: FbField) -> Result<f64, RmError>{
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        self.motor(idx).get_state(id, field)
+    }
+
+    /*
+    **  Make the current position of the motor the zero point of FbField::MultiTurn
+    */
+    pub fn zero_position(&self, id: impl Into<MotorId>) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        self.update_motor(idx, |motor| motor.zero_position(id))
+    }
+}
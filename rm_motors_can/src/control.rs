@@ -0,0 +1,222 @@
+use crate::{gear_ratio, i_max, MotorType, RPM_PER_ANGULAR, RPM_PER_V, V_MAX};
+use core::fmt;
+use core::time::Duration;
+
+/*
+**  Gains of one stage of the cascade
+**  Units are the stage's output per unit of error: radian (Position), rad/s (Velocity) or amp (Current)
+*/
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct PidGains {
+    pub kp: f64,
+    pub ki: f64, // per second
+    pub kd: f64, // seconds
+    pub kf: f64, // feed-forward, per unit of setpoint
+}
+
+/*
+**  The stages of the cascade, outermost first. Position mode runs all three, Velocity mode starts at Velocity.
+**  Position: output shaft rad -> Velocity setpoint (rad/s)
+**  Velocity: output shaft rad/s -> Current setpoint (A, or V for a GM6020 with the default pass-through Current stage)
+**  Current:  A -> the command sent to the motor, V for GM6020 or A for M3508/M2006
+**  A GM6020 is driven by voltage here, so its Current stage's setpoint is in V while the stage passes it through (kp, ki and kd
+**  of 0) and the measured current is ignored. Giving it kp, ki or kd closes a current loop: the Velocity stage's gains must then
+**  be in A, and the Current stage's in V per A. The Velocity stage's limit is reset to the current limit whenever it does so,
+**  and back to the voltage limit when it passes through again.
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+#[repr(C)]
+pub enum Stage { #[default] Position, Velocity, Current }
+const STAGES: usize = 3;
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Position => write!(f, "Position"),
+            Stage::Velocity => write!(f, "Velocity"),
+            Stage::Current  => write!(f, "Current"),
+        }
+    }
+}
+
+// Internal signals of a stage, for tuning. All are from the latest run_once.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+#[repr(C)]
+pub enum StageSignal { #[default] Setpoint, Measurement, Error, Integral, Output }
+
+// Starting points for tuning, with an unloaded motor
+pub fn default_position_gains(motor_type: MotorType) -> PidGains {
+    match motor_type {
+        MotorType::GM6020 => PidGains { kp: 20.0, ki: 0.0, kd: 0.0, kf: 0.0 },
+        MotorType::M3508  => PidGains { kp: 20.0, ki: 0.0, kd: 0.0, kf: 0.0 },
+        MotorType::M2006  => PidGains { kp: 20.0, ki: 0.0, kd: 0.0, kf: 0.0 },
+    }
+}
+
+/*
+**  PI + feed-forward, at the datasheet gear ratio
+**  The GM6020 feed-forward is the datasheet speed constant, i.e. the old open-loop velocity command
+*/
+pub fn default_velocity_gains(motor_type: MotorType) -> PidGains {
+    match motor_type {
+        MotorType::GM6020 => PidGains { kp: 0.5, ki: 10.0, kd: 0.0, kf: gear_ratio(motor_type)*RPM_PER_ANGULAR/RPM_PER_V },
+        MotorType::M3508  => PidGains { kp: 1.5, ki: 5.0,  kd: 0.0, kf: 0.0 },
+        MotorType::M2006  => PidGains { kp: 0.6, ki: 2.0,  kd: 0.0, kf: 0.0 },
+    }
+}
+
+// Pass the setpoint straight through: the motor's own firmware closes the current loop
+pub fn default_current_gains(_motor_type: MotorType) -> PidGains {
+    PidGains { kp: 0.0, ki: 0.0, kd: 0.0, kf: 1.0 }
+}
+
+impl PidGains {
+    // Whether a Current stage with these gains only passes its setpoint through, see Stage
+    pub fn passes_through(&self) -> bool {
+        self.kp == 0.0 && self.ki == 0.0 && self.kd == 0.0
+    }
+}
+
+// The Velocity stage's default limit, in V for a GM6020 while current_gains pass through and in A otherwise
+fn velocity_limit(motor_type: MotorType, current_gains: &PidGains) -> f64 {
+    match motor_type {
+        MotorType::GM6020 if current_gains.passes_through() => V_MAX,
+        _ => i_max(motor_type),
+    }
+}
+
+// Output shaft no-load speed (rad/s)
+fn max_velocity(motor_type: MotorType, gear_ratio: f64) -> f64 {
+    let rotor_rpm: f64 = match motor_type {
+        MotorType::GM6020 => V_MAX*RPM_PER_V,
+        MotorType::M3508  => 482.0*self::gear_ratio(motor_type),
+        MotorType::M2006  => 500.0*self::gear_ratio(motor_type),
+    };
+    rotor_rpm/RPM_PER_ANGULAR/gear_ratio
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct Pid {
+    pub gains:   PidGains,
+    integral:    f64,
+    setpoint:    f64,
+    measurement: f64,
+    output:      f64,
+    last:        Option<Duration>, // None until the first update, or after a reset
+}
+
+impl Pid {
+    pub fn new(gains: PidGains) -> Pid {
+        Pid { gains, ..Default::default() }
+    }
+
+    // Forget the integral and derivative history e.g. when the motor changes mode
+    pub fn reset(&mut self) {
+        *self = Pid::new(self.gains);
+    }
+
+    // Returns a command clamped to [-limit, limit]
+    pub fn update(&mut self, setpoint: f64, measurement: f64, limit: f64, now: Duration) -> f64 {
+        let error: f64 = setpoint - measurement;
+        let mut output: f64 = self.gains.kf*setpoint + self.gains.kp*error;
+        if let Some(last) = self.last {
+            let dt: f64 = now.saturating_sub(last).as_secs_f64();
+            if dt > 0.0 {
+                // Derivative on measurement so setpoint steps don't kick the motor
+                output -= self.gains.kd*(measurement - self.measurement)/dt;
+                // Only integrate while the output isn't saturated, so the integral doesn't wind up
+                let integral: f64 = self.integral + error*dt;
+                if (output + self.gains.ki*integral).abs() < limit {
+                    self.integral = integral;
+                }
+            }
+        }
+        output += self.gains.ki*self.integral;
+        self.setpoint = setpoint;
+        self.measurement = measurement;
+        self.output = output.clamp(-limit, limit);
+        self.last = Some(now);
+        self.output
+    }
+
+    pub fn signal(&self, signal: StageSignal) -> f64 {
+        match signal {
+            StageSignal::Setpoint    => self.setpoint,
+            StageSignal::Measurement => self.measurement,
+            StageSignal::Error       => self.setpoint - self.measurement,
+            StageSignal::Integral    => self.integral,
+            StageSignal::Output      => self.output,
+        }
+    }
+}
+
+// Position -> velocity -> current controllers of one motor
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct Cascade {
+    pub stages:      [Pid; STAGES],
+    pub limits:      [f64; STAGES], // Output limit of each stage
+    pub feedforward: [f64; STAGES], // Added to each stage's setpoint
+}
+
+impl Cascade {
+    pub fn new(motor_type: MotorType, gear_ratio: f64) -> Cascade {
+        // The default velocity feed-forward assumes the datasheet gear ratio
+        let mut velocity_gains: PidGains = default_velocity_gains(motor_type);
+        velocity_gains.kf *= gear_ratio/self::gear_ratio(motor_type);
+        let current_gains: PidGains = default_current_gains(motor_type);
+        let drive_max: f64 = match motor_type {
+            MotorType::GM6020 => V_MAX,
+            _ => i_max(motor_type),
+        };
+        Cascade {
+            stages: [
+                Pid::new(default_position_gains(motor_type)),
+                Pid::new(velocity_gains),
+                Pid::new(current_gains),
+            ],
+            limits: [max_velocity(motor_type, gear_ratio), velocity_limit(motor_type, &current_gains), drive_max],
+            feedforward: [0.0; STAGES],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.stages.iter_mut().for_each(Pid::reset);
+    }
+
+    /*
+    **  Set the gains of one stage and reset every stage's history
+    **  If the Current stage starts or stops passing its setpoint through, the Velocity stage's limit changes units on a GM6020,
+    **  so it is reset to the default for the new units.
+    */
+    pub fn set_gains(&mut self, motor_type: MotorType, stage: Stage, gains: PidGains) {
+        let current: usize = Stage::Current as usize;
+        let passed_through: bool = self.stages[current].gains.passes_through();
+        self.stages[stage as usize].gains = gains;
+        if self.stages[current].gains.passes_through() != passed_through {
+            self.limits[Stage::Velocity as usize] = velocity_limit(motor_type, &self.stages[current].gains);
+        }
+        self.reset();
+    }
+
+    /*
+    **  Run the stages from `first` inwards and return the command for the motor
+    **  feedforward: added to the setpoints along with the configured feed-forward, e.g. from a trajectory
+    **  measurements: output shaft position (rad) and velocity (rad/s), and current (A)
+    **  derating: fraction of the Velocity and Current stages' limits available, e.g. lowered for temperature. A fraction rather than
+    **  a limit, since the Velocity stage outputs A when the Current stage closes a loop on a GM6020 (see Stage).
+    */
+    pub fn update(&mut self, first: Stage, setpoint: f64, feedforward: [f64; STAGES], measurements: [f64; STAGES], derating: f64, now: Duration) -> f64 {
+        let mut setpoint: f64 = setpoint;
+        for (s, pid) in self.stages.iter_mut().enumerate() {
+            if s < first as usize {
+                // Clear the outer stages so they don't report stale signals or resume with an old integral
+                pid.reset();
+                continue;
+            }
+            let limit: f64 = if s == Stage::Position as usize { self.limits[s] } else { self.limits[s]*derating };
+            setpoint = pid.update(setpoint + self.feedforward[s] + feedforward[s], measurements[s], limit, now);
+        }
+        setpoint
+    }
+}
@@ -0,0 +1,299 @@
+use crate::motor::{self, CommandGroups, Motor};
+use crate::protocol::{self, Feedback};
+use crate::{gear_ratio, CmdMode, Fault, FaultRecord, FbField, LinkPolicy, LinkStatus, MotorId, MotorStatus, MotorType, MoveStatus, PidGains, RmError, Stage, StageSignal};
+use crate::ARR_LEN;
+use core::time::Duration;
+use embedded_can::blocking::Can;
+use embedded_can::{Error, Frame};
+
+/*
+**  The motors on a bus driven through any embedded_can::blocking::Can e.g. the CAN peripheral of a microcontroller
+**  The same motor logic as RmMotorsCan, without std, an allocator, threads or a clock: the application owns the loop.
+**  Call receive (or handle_frame from a receive interrupt) for each feedback frame, and run_once at a steady rate.
+**
+**  now: time since any fixed point, from a monotonic clock e.g. a hardware timer. The controllers and move_to run on it.
+*/
+pub struct MotorBus<C: Can> {
+    can:     C,
+    motors:  [Motor; ARR_LEN],
+    faulted: bool, // Latched by estop
+}
+
+impl<C: Can> MotorBus<C> {
+    pub fn new(can: C) -> MotorBus<C> {
+        MotorBus { can, motors: core::array::from_fn(motor::blank), faulted: false }
+    }
+
+    // The CAN driver, e.g. to reconfigure its filters
+    pub fn can(&mut self) -> &mut C {
+        &mut self.can
+    }
+
+    // Give back the CAN driver
+    pub fn release(self) -> C {
+        self.can
+    }
+
+    pub fn init_motor(&mut self, id: u8, motor_type: MotorType, mode: CmdMode) -> Result<(), RmError> {
+        self.init_motor_geared(id, motor_type, mode, gear_ratio(motor_type))
+    }
+
+    // See RmMotorsCan::init_motor_geared
+    pub fn init_motor_geared(&mut self, id: u8, motor_type: MotorType, mode: CmdMode, gear_ratio: f64) -> Result<(), RmError> {
+        let idx: usize = motor::check_init(id, motor_type, mode, gear_ratio, |i| (self.motors[i].motor_type, self.motors[i].mode))?;
+        self.motors[idx].init(motor_type, mode, gear_ratio);
+        Ok(())
+    }
+
+    /*
+    **  Wait for the next frame and parse it if it is feedback
+    **  returns: the type and ID of the motor it updated, if any
+    */
+    pub fn receive(&mut self, now: Duration) -> Result<Option<MotorId>, RmError> {
+        let frame: C::Frame = match self.can.receive() {
+            Ok(frame) => frame,
+            Err(err) => {
+                self.record_all(Fault::BusError, now);
+                return Err(RmError::Can(err.kind()));
+            },
+        };
+        Ok(self.handle_frame(&frame, now))
+    }
+
+    /*
+    **  Parse a frame received some other way e.g. in an interrupt
+    **  returns: the type and ID of the motor it updated, None if it isn't feedback from a motor on this bus
+    */
+    pub fn handle_frame(&mut self, frame: &C::Frame, now: Duration) -> Option<MotorId> {
+        let idx: usize = motor::feedback_index(protocol::data_frame_id(frame)?, |i| self.motors[i].mode)?;
+        let feedback: Feedback = Feedback::decode(frame.data())?;
+        self.motors[idx].apply_feedback(feedback, now);
+        Some(MotorId::Typed(self.motors[idx].motor_type, motor::id(idx)))
+    }
+
+    /*
+    **  Run the controllers and transmit the commands
+    **  An error from one motor's controller doesn't stop commands going to the rest, and is returned afterwards.
+    */
+    pub fn run_once(&mut self, now: Duration) -> Result<(), RmError> {
+        if self.faulted {
+            self.record_all(Fault::EStop, now);
+            self.stop_all();
+            return self.transmit_commands(now);
+        }
+        for (i, motor) in self.motors.iter_mut().enumerate() {
+            if motor.mode != CmdMode::Disabled {
+                motor.begin_cycle(now);
+                motor.feedback_age(motor::id(i), now).inspect_err(|_| {motor.record(Fault::StaleFeedback, -1.0);})?;
+            }
+        }
+        let mut r_controllers: Result<(), RmError> = Ok(());
+        for (i, motor) in self.motors.iter_mut().enumerate() {
+            if motor.mode != CmdMode::Disabled {
+                let r_link: Result<(), RmError> = motor.run_link(motor::id(i), now);
+                r_controllers = r_controllers.and(r_link).and(motor.run_watchdog(motor::id(i), now)).and(motor.run_thermal(motor::id(i)));
+            }
+        }
+        let bus_fault: bool = self.motors.iter().any(Motor::faults_bus);
+        for (i, motor) in self.motors.iter_mut().enumerate() {
+            if bus_fault && motor.mode != CmdMode::Disabled {
+                motor.stop();
+            }
+            else if motor.needs_controller() {
+                r_controllers = r_controllers.and(motor.run_controller(motor::id(i), now));
+            }
+        }
+        self.transmit_commands(now)?;
+        r_controllers
+    }
+
+    // Send the motors' commands, stopping at the first error
+    fn transmit_commands(&mut self, now: Duration) -> Result<(), RmError> {
+        let groups: CommandGroups = CommandGroups::new(|i| (self.motors[i].motor_type, self.motors[i].mode, self.motors[i].sent_command()));
+        for (frame_id, commands) in groups.iter() {
+            self.transmit(*frame_id, *commands).inspect_err(|_| self.record_all(Fault::BusError, now))?;
+        }
+        Ok(())
+    }
+
+    fn transmit(&mut self, frame_id: u16, commands: [i16; 4]) -> Result<(), RmError> {
+        let frame: C::Frame = protocol::command_frame(frame_id, commands).ok_or(RmError::Can(embedded_can::ErrorKind::Other))?;
+        self.can.transmit(&frame).map_err(|err| RmError::Can(err.kind()))
+    }
+
+    // See RmMotorsCan::record_all
+    fn record_all(&mut self, fault: Fault, now: Duration) {
+        for motor in self.motors.iter_mut().filter(|motor| motor.mode != CmdMode::Disabled) {
+            motor.tick(now);
+            motor.record(fault, 0.0);
+        }
+    }
+
+    fn stop_all(&mut self) {
+        for motor in self.motors.iter_mut().filter(|motor| motor.mode != CmdMode::Disabled) {
+            motor.stop();
+        }
+    }
+
+    /*
+    **  See RmMotorsCan::estop. The CAN driver is borrowed mutably, so to stop from an interrupt the application
+    **  has to share the MotorBus with it (e.g. in a critical section mutex) like any other method.
+    */
+    pub fn estop(&mut self) -> Result<(), RmError> {
+        self.faulted = true;
+        self.stop_all();
+        let mut r: Result<(), RmError> = Ok(());
+        for frame_id in protocol::CMD_IDS {
+            r = r.and(self.transmit(frame_id, [0; 4]));
+        }
+        r
+    }
+
+    pub fn faulted(&self) -> bool {
+        self.faulted
+    }
+
+    // See RmMotorsCan::clear_fault
+    pub fn clear_fault(&mut self) {
+        self.stop_all();
+        self.faulted = false;
+    }
+
+    // Table index and ID of a motor, see MotorId
+    fn resolve(&self, id: impl Into<MotorId>) -> Result<(usize, u8), RmError> {
+        motor::resolve(id.into(), |i| self.motors[i].mode)
+    }
+
+    // See RmMotorsCan::set_cmd
+    pub fn set_cmd(&mut self, id: impl Into<MotorId>, cmd: f64) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        if self.faulted {
+            return Err(RmError::EStopped);
+        }
+        self.motors[idx].set_cmd(id, cmd)
+    }
+
+    pub fn get_state(&self, id: impl Into<MotorId>, field: FbField) -> Result<f64, RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        self.motors[idx].get_state(id, field)
+    }
+
+    // See RmMotorsCan::set_command_timeout. The timeout is measured in run_once calls' now.
+    pub fn set_command_timeout(&mut self, id: impl Into<MotorId>, timeout_ms: u64, ramp_ms: u64) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.motors[idx].set_command_timeout(timeout_ms, ramp_ms);
+        Ok(())
+    }
+
+    // See RmMotorsCan::set_link_policy. The timeout is measured in run_once calls' now.
+    pub fn set_link_policy(&mut self, id: impl Into<MotorId>, policy: LinkPolicy, timeout_ms: u64) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.motors[idx].set_link_policy(policy, timeout_ms)
+    }
+
+    pub fn link_status(&self, id: impl Into<MotorId>, now: Duration) -> Result<LinkStatus, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motors[idx].link_status(now))
+    }
+
+    // See RmMotorsCan::clear_link_fault
+    pub fn clear_link_fault(&mut self, id: impl Into<MotorId>, now: Duration) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        self.motors[idx].clear_link_fault(id, now)
+    }
+
+    // See RmMotorsCan::set_temperature_limits
+    pub fn set_temperature_limits(&mut self, id: impl Into<MotorId>, derate_c: u8, max_c: u8) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.motors[idx].set_temperature_limits(derate_c, max_c)
+    }
+
+    // See RmMotorsCan::command_timed_out
+    pub fn command_timed_out(&self, id: impl Into<MotorId>) -> Result<bool, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motors[idx].watchdog.tripped.is_some())
+    }
+
+    // See RmMotorsCan::motor_status
+    pub fn motor_status(&self, id: impl Into<MotorId>) -> Result<MotorStatus, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motors[idx].faults.status)
+    }
+
+    // See RmMotorsCan::fault_history. The times are the now of the calls that found the faults.
+    pub fn fault_history(&self, id: impl Into<MotorId>) -> Result<impl Iterator<Item = FaultRecord> + '_, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motors[idx].faults.history())
+    }
+
+    pub fn clear_faults(&mut self, id: impl Into<MotorId>) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.motors[idx].clear_faults();
+        Ok(())
+    }
+
+    // Time since the motor's latest feedback, StaleFeedback if it has never reported any
+    pub fn feedback_age(&self, id: impl Into<MotorId>, now: Duration) -> Result<Duration, RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        self.motors[idx].feedback_age(id, now)
+    }
+
+    pub fn zero_position(&mut self, id: impl Into<MotorId>) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        self.motors[idx].zero_position(id)
+    }
+
+    // See RmMotorsCan::set_stage_gains
+    pub fn set_stage_gains(&mut self, id: impl Into<MotorId>, stage: Stage, kp: f64, ki: f64, kd: f64, kf: f64) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        self.motors[idx].set_stage_gains(id, stage, PidGains { kp, ki, kd, kf })
+    }
+
+    pub fn set_position_gains(&mut self, id: impl Into<MotorId>, kp: f64, ki: f64, kd: f64) -> Result<(), RmError> {
+        self.set_stage_gains(id, Stage::Position, kp, ki, kd, 0.0)
+    }
+
+    pub fn set_velocity_gains(&mut self, id: impl Into<MotorId>, kp: f64, ki: f64, kf: f64) -> Result<(), RmError> {
+        self.set_stage_gains(id, Stage::Velocity, kp, ki, 0.0, kf)
+    }
+
+    pub fn set_stage_limit(&mut self, id: impl Into<MotorId>, stage: Stage, limit: f64) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.motors[idx].set_stage_limit(stage, limit)
+    }
+
+    pub fn set_feedforward(&mut self, id: impl Into<MotorId>, stage: Stage, value: f64) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.motors[idx].set_feedforward(stage, value)
+    }
+
+    pub fn get_stage_signal(&self, id: impl Into<MotorId>, stage: Stage, signal: StageSignal) -> Result<f64, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motors[idx].stage_signal(stage, signal))
+    }
+
+    // See RmMotorsCan::move_to
+    pub fn move_to(&mut self, id: impl Into<MotorId>, target: f64, max_vel: f64, max_accel: f64, now: Duration) -> Result<(), RmError> {
+        self.move_to_s_curve(id, target, max_vel, max_accel, 0.0, now)
+    }
+
+    // See RmMotorsCan::move_to_s_curve
+    pub fn move_to_s_curve(&mut self, id: impl Into<MotorId>, target: f64, max_vel: f64, max_accel: f64, max_jerk: f64, now: Duration) -> Result<(), RmError> {
+        let (idx, id): (usize, u8) = self.resolve(id)?;
+        if self.faulted {
+            return Err(RmError::EStopped);
+        }
+        self.motors[idx].move_to(id, target, max_vel, max_accel, max_jerk, now)
+    }
+
+    pub fn move_status(&self, id: impl Into<MotorId>, now: Duration) -> Result<MoveStatus, RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        Ok(self.motors[idx].move_status(now))
+    }
+
+    pub fn cancel_move(&mut self, id: impl Into<MotorId>, now: Duration) -> Result<(), RmError> {
+        let idx: usize = self.resolve(id)?.0;
+        self.motors[idx].cancel_move(now);
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{CmdMode, FbField, MotorType};
+use core::fmt;
+use core::time::Duration;
+use embedded_can::ErrorKind;
+#[cfg(feature = "std")]
+use std::io;
+
+#[derive(Debug)]
+pub enum RmError {
+    #[cfg(feature = "std")]
+    Io(io::Error),                                                       // Reading or writing the CAN bus failed
+    Can(ErrorKind),                                                      // Reading or writing an embedded_can bus failed
+    NotInitialized,                                                      // No transport is attached to the handle
+    #[cfg(feature = "std")]
+    InvalidInterface(String),                                            // The interface name couldn't be opened or parsed
+    #[cfg(feature = "std")]
+    UnknownMotor(String),                                                // No motor has the name, or no bus the index, given to a BusManager
+    IdOutOfRange      { id: u8, min: u8, max: u8 },
+    IdCollision       { motor_type: MotorType, id: u8, other: MotorType, other_id: u8 }, // e.g. GM6020 1 and M3508 5 share CAN addresses
+    AmbiguousId       { id: u8 },                                        // Motors of both families have the ID, see MotorId
+    UnsupportedMode   { id: u8, motor_type: MotorType, mode: CmdMode },
+    OverTemperature   { id: u8, temperature: u16, limit: u8 },
+    UnsupportedField  { id: u8, motor_type: MotorType, field: FbField },
+    StaleFeedback     { id: u8, age: Option<Duration> },                 // age is None if the motor never responded
+    ConflictingController,                                               // Another program is already sending commands on the bus
+    InvalidParameter  { name: &'static str, value: f64 },
+    CommandTimeout    { id: u8, age: Duration },                         // No set_cmd within the motor's command timeout, so it was stopped
+    LinkFault         { id: u8 },                                        // The motor's link was lost and its policy stopped it until clear_link_fault
+    EStopped,                                                            // The bus was emergency stopped, commands are rejected until clear_fault
+}
+
+impl fmt::Display for RmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            RmError::Io(err)                 => write!(f, "CAN bus I/O error: {}", err),
+            RmError::Can(kind)               => write!(f, "CAN bus error: {}", kind),
+            RmError::NotInitialized          => write!(f, "Transport not initialized"),
+            #[cfg(feature = "std")]
+            RmError::InvalidInterface(msg)   => write!(f, "Invalid interface: {}", msg),
+            #[cfg(feature = "std")]
+            RmError::UnknownMotor(motor)     => write!(f, "Unknown motor {}", motor),
+            RmError::IdOutOfRange {id, min, max} => write!(f, "id out of range [{}, {}]: {}", min, max, id),
+            RmError::IdCollision {motor_type, id, other, other_id} => write!(f, "{}:{} cannot coexist with {}:{}, they share CAN IDs", motor_type, id, other, other_id),
+            RmError::AmbiguousId {id}        => write!(f, "Motors of both families have ID {}, give the motor type as well", id),
+            RmError::UnsupportedMode {id, motor_type, mode} => write!(f, "Motor {} is an {}, which does not accept {} commands", id, motor_type, mode),
+            RmError::OverTemperature {id, temperature, limit} => write!(f, "Motor {} temperature overload [{}]: {}", id, limit, temperature),
+            RmError::UnsupportedField {id, motor_type, field} => write!(f, "Motor {} is an {}, which does not report {}", id, motor_type, field),
+            RmError::StaleFeedback {id, age: None} => write!(f, "Motor {} never responded.", id),
+            RmError::StaleFeedback {id, age: Some(age)} => write!(f, "Haven't heard from Motor {} in {}ms", id, age.as_millis()),
+            RmError::ConflictingController   => write!(f, "Another program is sending GM6020 commands already"),
+            RmError::InvalidParameter {name, value} => write!(f, "Invalid {}: {}", name, value),
+            RmError::CommandTimeout {id, age} => write!(f, "No command for Motor {} in {}ms, stopping it", id, age.as_millis()),
+            RmError::LinkFault {id}          => write!(f, "Motor {} lost its link and was stopped, clear_link_fault to resume", id),
+            RmError::EStopped                => write!(f, "Emergency stopped, clear_fault to resume"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RmError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for RmError {
+    fn from(err: io::Error) -> Self { RmError::Io(err) }
+}
@@ -0,0 +1,198 @@
+use crate::{lock, RmError, RmMotorsCan};
+use arc_swap::ArcSwap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Loop-period statistics, see get_loop_stat
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+#[repr(C)]
+pub enum LoopStat { #[default] Iterations, Overruns, Errors, LastPeriod, MeanPeriod, MinPeriod, MaxPeriod }
+
+impl fmt::Display for LoopStat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoopStat::Iterations => write!(f, "Iterations"),
+            LoopStat::Overruns   => write!(f, "Overruns"),
+            LoopStat::Errors     => write!(f, "Errors"),
+            LoopStat::LastPeriod => write!(f, "Last Period"),
+            LoopStat::MeanPeriod => write!(f, "Mean Period"),
+            LoopStat::MinPeriod  => write!(f, "Min Period"),
+            LoopStat::MaxPeriod  => write!(f, "Max Period"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Stats {
+    iterations: u64,
+    overruns:   u64, // run_once took longer than the period
+    errors:     u64, // run_once returned an error
+    last:       Duration,
+    total:      Duration,
+    min:        Duration,
+    max:        Duration,
+}
+
+#[derive(Default)]
+struct Shared {
+    stop:      AtomicBool,
+    period_ns: AtomicU64,
+    stats:     ArcSwap<Stats>, // Only written by the loop, so reading it never holds the loop up
+}
+
+// The library-owned thread calling run_once, of one bus or of a BusManager
+#[derive(Default)]
+pub(crate) struct IoLoop {
+    shared: Arc<Shared>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl IoLoop {
+    /*
+    **  Start a thread named name that calls step every period_us microseconds. If the loop is already running this only changes its period,
+    **  and if it has been stopped but not yet exited this waits for it to exit first.
+    **  step: run_once, returning None once its owner has been dropped, which ends the loop
+    */
+    pub(crate) fn start(&self, period_us: u64, name: &str, step: impl FnMut() -> Option<Result<(), RmError>> + Send + 'static) -> Result<(), RmError> {
+        if period_us == 0 {
+            return Err(RmError::InvalidParameter { name: "period_us", value: 0.0 });
+        }
+        let mut handle = lock(&self.handle);
+        self.shared.period_ns.store(period_us*1000, Ordering::Relaxed);
+        if self.shared.stop.load(Ordering::Relaxed) {
+            // A stopped loop may still be in its last iteration, and would exit after this returns. Wait for it to start a new one.
+            match handle.take() {
+                // Restarted from its own step: it carries on once stop is cleared below
+                Some(h) if h.thread().id() == thread::current().id() => *handle = Some(h),
+                Some(h) => join(h),
+                None => {},
+            }
+        }
+        self.shared.stop.store(false, Ordering::Relaxed);
+        if handle.as_ref().is_some_and(|h| !h.is_finished()) {
+            return Ok(());
+        }
+        self.shared.stats.store(Arc::default());
+        let shared: Arc<Shared> = self.shared.clone();
+        *handle = Some(thread::Builder::new().name(name.to_string()).spawn(move || run(step, shared))?);
+        Ok(())
+    }
+
+    // Ask the loop to stop after its current iteration. Does nothing if it isn't running.
+    pub(crate) fn stop(&self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+
+    // Wait for the loop to exit after stop
+    pub(crate) fn join(&self) {
+        let handle: Option<JoinHandle<()>> = lock(&self.handle).take();
+        if let Some(handle) = handle {
+            if handle.thread().id() == thread::current().id() {
+                // Joining itself would deadlock. The loop exits once this iteration returns.
+                return;
+            }
+            join(handle);
+        }
+    }
+
+    // See RmMotorsCan::get_loop_stat
+    pub(crate) fn stat(&self, stat: LoopStat) -> f64 {
+        let stats: Stats = **self.shared.stats.load();
+        let us = |d: Duration| d.as_secs_f64()*1e6;
+        match stat {
+            LoopStat::Iterations => stats.iterations as f64,
+            LoopStat::Overruns   => stats.overruns as f64,
+            LoopStat::Errors     => stats.errors as f64,
+            LoopStat::LastPeriod => us(stats.last),
+            LoopStat::MeanPeriod => if stats.iterations > 1 { us(stats.total)/(stats.iterations - 1) as f64 } else { 0.0 },
+            LoopStat::MinPeriod  => us(stats.min),
+            LoopStat::MaxPeriod  => us(stats.max),
+        }
+    }
+}
+
+impl RmMotorsCan {
+    /*
+    **  Start a thread that calls run_once every period_us microseconds, so set_cmd takes effect within one period
+    **  If the loop is already running this only changes its period.
+    **  The loop stops with stop_loop, cleanup, or when the last handle to rm_motors_can is dropped.
+    */
+    pub fn start_loop(self: &Arc<Self>, period_us: u64) -> Result<(), RmError> {
+        // Only hold a weak reference, otherwise the thread would keep rm_motors_can alive forever
+        let weak: Weak<RmMotorsCan> = Arc::downgrade(self);
+        self.io_loop.start(period_us, "rm_motors_can", move || Some(weak.upgrade()?.run_once()))
+    }
+
+    // Ask the loop to stop after its current iteration. Does nothing if it isn't running.
+    pub fn stop_loop(&self) -> Result<(), RmError> {
+        self.io_loop.stop();
+        Ok(())
+    }
+
+    // Wait for the loop to exit after stop_loop
+    pub fn join_loop(&self) -> Result<(), RmError> {
+        self.io_loop.join();
+        Ok(())
+    }
+
+    /*
+    **  Statistics of the loop since start_loop. Periods are in microseconds, measured between the starts of consecutive iterations.
+    */
+    pub fn get_loop_stat(&self, stat: LoopStat) -> Result<f64, RmError> {
+        Ok(self.io_loop.stat(stat))
+    }
+}
+
+fn join(handle: JoinHandle<()>) {
+    if handle.join().is_err() {
+        eprintln!("The I/O loop panicked");
+    }
+}
+
+fn run(mut step: impl FnMut() -> Option<Result<(), RmError>>, shared: Arc<Shared>) {
+    let mut next: Instant = Instant::now();
+    let mut previous: Option<Instant> = None;
+    let mut failing: bool = false;
+    let mut stats: Stats = Stats::default();
+    while !shared.stop.load(Ordering::Relaxed) {
+        let start: Instant = Instant::now();
+        let result: Result<(), RmError> = match step() {
+            Some(result) => result,
+            None => return,
+        };
+        stats.iterations += 1;
+        if let Some(previous) = previous {
+            let period: Duration = start - previous;
+            stats.last = period;
+            stats.total += period;
+            stats.max = stats.max.max(period);
+            stats.min = if stats.iterations == 2 { period } else { stats.min.min(period) };
+        }
+        if let Err(e) = result {
+            stats.errors += 1;
+            // Only report the first of a run of errors, rather than flooding at the loop rate
+            if !failing { eprintln!("{}", e); }
+            failing = true;
+        }
+        else {
+            failing = false;
+        }
+        previous = Some(start);
+
+        // Sleep until the next deadline rather than for a fixed time, so the period doesn't drift
+        next += Duration::from_nanos(shared.period_ns.load(Ordering::Relaxed));
+        let now: Instant = Instant::now();
+        if next > now {
+            thread::sleep(next - now);
+        }
+        else {
+            // Don't try to catch up with a burst of iterations
+            stats.overruns += 1;
+            next = now;
+        }
+        shared.stats.store(Arc::new(stats));
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{CanTransport, CmdMode, FbField, LoopStat, MotorType, MoveStatus, RmError, RmMotorsCan, Stage, StageSignal};
+use std::sync::Arc;
+
+//////
+// The free-function API from before the methods on RmMotorsCan, kept so existing code still builds.
+// Each forwards to the method of the same name. Functions without a value to return return Ok(0).
+//////
+
+macro_rules! shim {
+    ($name:ident, ($($param_name:ident: $param_type:ty),*)) => {
+        #[deprecated(note = "use the RmMotorsCan method of the same name")]
+        pub fn $name(rm_motors_can: Arc<RmMotorsCan>, $($param_name: $param_type),*) -> Result<i32, RmError> {
+            rm_motors_can.$name($($param_name),*).map(|()| 0)
+        }
+    };
+    ($name:ident, ($($param_name:ident: $param_type:ty),*), $return_type:ty) => {
+        #[deprecated(note = "use the RmMotorsCan method of the same name")]
+        pub fn $name(rm_motors_can: Arc<RmMotorsCan>, $($param_name: $param_type),*) -> Result<$return_type, RmError> {
+            rm_motors_can.$name($($param_name),*)
+        }
+    };
+}
+
+#[deprecated(note = "use RmMotorsCan::open or RmMotorsCan::builder")]
+pub fn init_bus(interface: &str) -> Result<Arc<RmMotorsCan>, RmError> {
+    RmMotorsCan::open(interface)
+}
+
+#[deprecated(note = "use RmMotorsCan::with_transport or RmMotorsCan::builder")]
+pub fn init_bus_with_transport(transport: Box<dyn CanTransport>) -> Result<Arc<RmMotorsCan>, RmError> {
+    RmMotorsCan::with_transport(transport)
+}
+
+shim!(init_motor, (id: u8, motor_type: MotorType, mode: CmdMode));
+shim!(init_motor_geared, (id: u8, motor_type: MotorType, mode: CmdMode, gear_ratio: f64));
+shim!(cleanup,    (period_ms: u64));
+shim!(run_once,   ());
+shim!(set_cmd,    (id: u8, cmd: f64));
+shim!(get_state,  (id: u8, field: FbField), f64);
+shim!(zero_position, (id: u8));
+shim!(set_position_gains, (id: u8, kp: f64, ki: f64, kd: f64));
+shim!(set_velocity_gains, (id: u8, kp: f64, ki: f64, kf: f64));
+shim!(set_stage_gains, (id: u8, stage: Stage, kp: f64, ki: f64, kd: f64, kf: f64));
+shim!(set_stage_limit, (id: u8, stage: Stage, limit: f64));
+shim!(set_feedforward, (id: u8, stage: Stage, value: f64));
+shim!(get_stage_signal, (id: u8, stage: Stage, signal: StageSignal), f64);
+shim!(move_to, (id: u8, target: f64, max_vel: f64, max_accel: f64));
+shim!(move_to_s_curve, (id: u8, target: f64, max_vel: f64, max_accel: f64, max_jerk: f64));
+shim!(move_status, (id: u8), MoveStatus);
+shim!(cancel_move, (id: u8));
+shim!(start_loop, (period_us: u64));
+shim!(stop_loop,  ());
+shim!(join_loop,  ());
+shim!(get_loop_stat, (stat: LoopStat), f64);
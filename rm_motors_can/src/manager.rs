@@ -0,0 +1,242 @@
+use crate::bus::Commands;
+use crate::{lock, CmdMode, FaultRecord, FbField, IoLoop, LoopStat, MotorId, MotorSnapshot, MotorStatus, MotorType, MoveStatus, RmError, RmMotorsCan};
+use std::collections::HashMap;
+use std::fmt;
+use std::panic;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+/*
+**  A motor of a BusManager: the index of its bus and its MotorId, or the name given to it with name_motor
+**  (bus, id), (bus, (MotorType, id)) and names all convert e.g. manager.set_cmd((1, 5), 2.0) or manager.set_cmd("yaw", 2.0)
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MotorAddr<'a> { On(usize, MotorId), Named(&'a str) }
+impl From<(usize, u8)> for MotorAddr<'_> {
+    fn from((bus, id): (usize, u8)) -> Self { MotorAddr::On(bus, id.into()) }
+}
+impl From<(usize, (MotorType, u8))> for MotorAddr<'_> {
+    fn from((bus, motor): (usize, (MotorType, u8))) -> Self { MotorAddr::On(bus, motor.into()) }
+}
+impl From<(usize, MotorId)> for MotorAddr<'_> {
+    fn from((bus, id): (usize, MotorId)) -> Self { MotorAddr::On(bus, id) }
+}
+impl<'a> From<&'a str> for MotorAddr<'a> {
+    fn from(name: &'a str) -> Self { MotorAddr::Named(name) }
+}
+impl fmt::Display for MotorAddr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MotorAddr::On(bus, id)  => write!(f, "{} on bus {}", id, bus),
+            MotorAddr::Named(name) => write!(f, "\"{}\"", name),
+        }
+    }
+}
+
+/*
+**  Several buses driven as one e.g. can0 for the chassis and can1 for the gimbal, from a single loop
+**  Each bus keeps its own receive thread. Motors are addressed by bus and ID or by name (see MotorAddr),
+**  and bus() gives the RmMotorsCan handle of a bus for everything the manager doesn't forward, e.g. the gains.
+*/
+pub struct BusManager {
+    buses:   Vec<Arc<RmMotorsCan>>,
+    names:   Mutex<HashMap<String, (usize, MotorId)>>, // From name_motor
+    io_loop: IoLoop,
+}
+
+impl BusManager {
+    /*
+    **  Open several buses, see RmMotorsCan::open. Each bus's index is its position in interfaces.
+    */
+    pub fn open(interfaces: &[&str]) -> Result<Arc<BusManager>, RmError> {
+        // Each one listens for another controller before taking over its bus, so open them all at once
+        let buses: Vec<Result<Arc<RmMotorsCan>, RmError>> = thread::scope(|scope| {
+            let threads: Vec<thread::ScopedJoinHandle<Result<Arc<RmMotorsCan>, RmError>>> = interfaces.iter()
+                .map(|interface| scope.spawn(move || RmMotorsCan::open(interface)))
+                .collect();
+            threads.into_iter().map(|thread| thread.join().unwrap_or_else(|e| panic::resume_unwind(e))).collect()
+        });
+        Ok(BusManager::new(buses.into_iter().collect::<Result<_, _>>()?))
+    }
+
+    // Manage buses that are already open e.g. from RmMotorsCan::builder. Their loops shouldn't be started as well.
+    pub fn new(buses: Vec<Arc<RmMotorsCan>>) -> Arc<BusManager> {
+        Arc::new(BusManager { buses, names: Mutex::default(), io_loop: IoLoop::default() })
+    }
+
+    pub fn bus(&self, bus: usize) -> Result<&Arc<RmMotorsCan>, RmError> {
+        self.buses.get(bus).ok_or_else(|| RmError::UnknownMotor(format!("on bus {}, there are {} buses", bus, self.buses.len())))
+    }
+
+    pub fn bus_count(&self) -> usize {
+        self.buses.len()
+    }
+
+    pub fn init_motor(&self, bus: usize, id: u8, motor_type: MotorType, mode: CmdMode) -> Result<(), RmError> {
+        self.bus(bus)?.init_motor(id, motor_type, mode)
+    }
+
+    pub fn init_motor_geared(&self, bus: usize, id: u8, motor_type: MotorType, mode: CmdMode, gear_ratio: f64) -> Result<(), RmError> {
+        self.bus(bus)?.init_motor_geared(id, motor_type, mode, gear_ratio)
+    }
+
+    /*
+    **  Give a motor a name to address it by. A name already in use moves to the new motor.
+    */
+    pub fn name_motor(&self, name: &str, bus: usize, id: impl Into<MotorId>) -> Result<(), RmError> {
+        self.bus(bus)?;
+        lock(&self.names).insert(name.to_string(), (bus, id.into()));
+        Ok(())
+    }
+
+    /*
+    **  The bus a motor is on and its MotorId there, to call any RmMotorsCan method on it
+    */
+    pub fn locate<'a>(&self, motor: impl Into<MotorAddr<'a>>) -> Result<(&Arc<RmMotorsCan>, MotorId), RmError> {
+        let (bus, id): (usize, MotorId) = match motor.into() {
+            MotorAddr::On(bus, id) => (bus, id),
+            MotorAddr::Named(name) => *lock(&self.names).get(name).ok_or_else(|| RmError::UnknownMotor(format!("\"{}\"", name)))?,
+        };
+        Ok((self.bus(bus)?, id))
+    }
+
+    pub fn set_cmd<'a>(&self, motor: impl Into<MotorAddr<'a>>, cmd: f64) -> Result<(), RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.set_cmd(id, cmd)
+    }
+
+    pub fn get_state<'a>(&self, motor: impl Into<MotorAddr<'a>>, field: FbField) -> Result<f64, RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.get_state(id, field)
+    }
+
+    pub fn zero_position<'a>(&self, motor: impl Into<MotorAddr<'a>>) -> Result<(), RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.zero_position(id)
+    }
+
+    pub fn move_to<'a>(&self, motor: impl Into<MotorAddr<'a>>, target: f64, max_vel: f64, max_accel: f64) -> Result<(), RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.move_to(id, target, max_vel, max_accel)
+    }
+
+    pub fn move_to_s_curve<'a>(&self, motor: impl Into<MotorAddr<'a>>, target: f64, max_vel: f64, max_accel: f64, max_jerk: f64) -> Result<(), RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.move_to_s_curve(id, target, max_vel, max_accel, max_jerk)
+    }
+
+    pub fn move_status<'a>(&self, motor: impl Into<MotorAddr<'a>>) -> Result<MoveStatus, RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.move_status(id)
+    }
+
+    pub fn cancel_move<'a>(&self, motor: impl Into<MotorAddr<'a>>) -> Result<(), RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.cancel_move(id)
+    }
+
+    pub fn motor_status<'a>(&self, motor: impl Into<MotorAddr<'a>>) -> Result<MotorStatus, RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.motor_status(id)
+    }
+
+    pub fn fault_history<'a>(&self, motor: impl Into<MotorAddr<'a>>) -> Result<Vec<FaultRecord>, RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.fault_history(id)
+    }
+
+    pub fn clear_faults<'a>(&self, motor: impl Into<MotorAddr<'a>>) -> Result<(), RmError> {
+        let (bus, id): (&Arc<RmMotorsCan>, MotorId) = self.locate(motor)?;
+        bus.clear_faults(id)
+    }
+
+    /*
+    **  run_once on every bus. All of them check their feedback and run their controllers before any sends,
+    **  so the commands of one iteration go out together. An error on one bus doesn't stop the others, and the first is returned.
+    */
+    pub fn run_once(&self) -> Result<(), RmError> {
+        let prepared: Vec<Result<Commands, RmError>> = self.buses.iter().map(|bus| bus.prepare_commands()).collect();
+        let mut r: Result<(), RmError> = Ok(());
+        for (bus, prepared) in self.buses.iter().zip(prepared) {
+            r = r.and(prepared.and_then(|(frames, r_controllers, estops)| bus.send_commands(&frames, estops).and(r_controllers)));
+        }
+        r
+    }
+
+    /*
+    **  RmMotorsCan::estop on every bus, latching all of them before sending on any. Returns the first error of any bus.
+    **  Safe to call from another thread or a signal handler, under the same conditions.
+    */
+    pub fn estop(&self) -> Result<(), RmError> {
+        for bus in self.buses.iter() {
+            bus.latch_fault();
+        }
+        let mut r: Result<(), RmError> = Ok(());
+        for bus in self.buses.iter() {
+            r = r.and(bus.estop());
+        }
+        r
+    }
+
+    // Whether any bus is latched by estop
+    pub fn faulted(&self) -> Result<bool, RmError> {
+        Ok(self.buses.iter().any(|bus| bus.is_faulted()))
+    }
+
+    // RmMotorsCan::clear_fault on every bus. Returns the first error of any bus.
+    pub fn clear_fault(&self) -> Result<(), RmError> {
+        let mut r: Result<(), RmError> = Ok(());
+        for bus in self.buses.iter() {
+            r = r.and(bus.clear_fault());
+        }
+        r
+    }
+
+    /*
+    **  Start a thread that calls run_once every period_us microseconds, see RmMotorsCan::start_loop
+    */
+    pub fn start_loop(self: &Arc<Self>, period_us: u64) -> Result<(), RmError> {
+        let weak: Weak<BusManager> = Arc::downgrade(self);
+        self.io_loop.start(period_us, "rm_motors_can_manager", move || Some(weak.upgrade()?.run_once()))
+    }
+
+    pub fn stop_loop(&self) -> Result<(), RmError> {
+        self.io_loop.stop();
+        Ok(())
+    }
+
+    pub fn join_loop(&self) -> Result<(), RmError> {
+        self.io_loop.join();
+        Ok(())
+    }
+
+    pub fn get_loop_stat(&self, stat: LoopStat) -> Result<f64, RmError> {
+        Ok(self.io_loop.stat(stat))
+    }
+
+    /*
+    **  Stop the loop, then ramp the motors of every bus down to 0 together, see RmMotorsCan::cleanup
+    **  Returns the first error of any bus.
+    */
+    pub fn cleanup(&self, period_ms: u64) -> Result<(), RmError> {
+        self.stop_loop()?;
+        self.join_loop()?;
+        thread::scope(|scope| {
+            let threads: Vec<thread::ScopedJoinHandle<Result<(), RmError>>> = self.buses.iter().map(|bus| scope.spawn(move || bus.cleanup(period_ms))).collect();
+            let mut r: Result<(), RmError> = Ok(());
+            for thread in threads {
+                r = r.and(thread.join().unwrap_or_else(|e| panic::resume_unwind(e)));
+            }
+            r
+        })
+    }
+
+    /*
+    **  State of the initialized motors of every bus, see RmMotorsCan::snapshot
+    */
+    pub fn snapshot(&self) -> Vec<MotorSnapshot> {
+        self.buses.iter().enumerate()
+            .flat_map(|(i, bus)| bus.snapshot().into_iter().map(move |motor| MotorSnapshot { bus: i, ..motor }))
+            .collect()
+    }
+}
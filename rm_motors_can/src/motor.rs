@@ -0,0 +1,764 @@
+use crate::control::Cascade;
+use crate::protocol::{self, Feedback};
+use crate::status::{Fault, FaultLog};
+use crate::trajectory::{Move, MoveStatus, Trajectory};
+use crate::{drive, gear_ratio, i_cmd_max, i_max, id_max, nm_per_a, CmdMode, Family, FbField, LinkPolicy, LinkStatus, MotorId, MotorType, PidGains, RmError, Stage, StageSignal};
+use crate::{ARR_LEN, COUNTS_PER_REV, FAMILY_LEN, ID_MIN, POS_MAX, RPM_PER_ANGULAR, TEMP_DERATE, TEMP_HYSTERESIS, TEMP_MAX, V_CMD_MAX, V_MAX};
+use core::f64::consts::PI;
+use core::fmt;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use crate::TimestampSource;
+
+//////
+// The state of the motors on a bus and everything done to it, without any I/O, locking or clock.
+// Shared by RmMotorsCan, which keeps each Motor in a lock-free snapshot, and MotorBus, which owns them outright.
+// Times are a Duration since any fixed point of a monotonic clock, chosen by the caller.
+//////
+
+/*
+**  Everything known about one motor
+**  RmMotorsCan only ever replaces it as a whole (see RmMotorsCan::update_motor), so readers get a consistent snapshot e.g. feedback
+**  together with the gear ratio it is scaled by, without taking a lock the control loop or receive thread could be waiting on.
+*/
+#[derive(Clone, Default, Debug)]
+pub(crate) struct Motor {
+    pub motor_type:  MotorType,
+    pub mode:        CmdMode,
+    pub gear_ratio:  f64,              // 0 until the motor is initialized
+    pub command:     i16,              // Sent by the next run_once
+    pub received:    Option<Duration>, // When the latest feedback arrived, None until the first
+    #[cfg(feature = "std")]
+    pub rx_source:   TimestampSource,  // The clock received was taken from
+    #[cfg(feature = "std")]
+    pub rx_hardware: Option<Duration>, // The CAN adapter's timestamp of the latest feedback, if it has one
+    pub feedback:    Feedback,
+    pub thermal:     u8,               // Temperature the limits are derated for: the latest, or up to TEMP_HYSTERESIS above it while cooling
+    pub temp_limits: Option<(u8, u8)>, // From set_temperature_limits, None for TEMP_DERATE and TEMP_MAX
+    pub turns:       i64,              // Number of times the encoder has rolled over, negative when turning backwards
+    pub zero:        i64,              // Encoder count treated as 0 for the multi-turn position
+    pub setpoint:    f64,              // Target of the closed-loop modes
+    pub hold:        bool,             // Initialized in Position mode before any feedback: hold the first feedback's position, unless commanded first
+    pub cascade:     Cascade,
+    pub active_move: Option<Move>,     // From move_to
+    pub watchdog:    Watchdog,
+    pub link:        Link,
+    pub faults:      FaultLog,
+    pub clock:       Duration,         // The latest time the motor has been given, which faults found without one are recorded at
+    pub notice:      Option<Notice>,   // A warning from the latest change, printed by RmMotorsCan::update_motor. MotorBus has nowhere to print it.
+}
+
+// Something worth telling the application about, without printing from Motor itself: RmMotorsCan may run a change more than once
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Notice {
+    LinkLost { motor_type: MotorType, id: u8, timeout: Duration },
+    LinkBack { motor_type: MotorType, id: u8 },
+    Clamped  { drive: CmdMode, max: f64, cmd: f64 },
+}
+impl fmt::Display for Notice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Notice::LinkLost { motor_type, id, timeout } => write!(f, "Haven't heard from {}:{} in over {}ms. Is it powered and connected?", motor_type, id, timeout.as_millis()),
+            Notice::LinkBack { motor_type, id } => write!(f, "{}:{} is back", motor_type, id),
+            Notice::Clamped { drive, max, cmd } => {
+                let quantity: &str = if *drive == CmdMode::Voltage { "voltage" } else { "current" };
+                write!(f, "Warning: {} out of range [{}, {}]: {}. Clamping.", quantity, -max, max, cmd)
+            },
+        }
+    }
+}
+
+const STALE_AGE: Duration = Duration::from_millis(10); // 10 missed feedback frames
+const LINK_TIMEOUT: Duration = Duration::from_millis(100);
+
+// From set_link_policy, see Motor::run_link
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Link {
+    pub policy:  LinkPolicy,
+    pub timeout: Duration,
+    pub lost:    bool, // As of the last run_once
+    pub latched: bool, // Disable or FaultBus has acted, until clear_link_fault
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Link { policy: LinkPolicy::Warn, timeout: LINK_TIMEOUT, lost: false, latched: false }
+    }
+}
+
+// From set_command_timeout, see Motor::run_watchdog
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct Watchdog {
+    pub timeout:   Option<Duration>,        // None if it is off
+    pub ramp:      Duration,                // Time to ramp the command down over once it trips, 0 to zero it at once
+    pub refreshed: bool,                    // A command was given since the last run_once
+    pub last_cmd:  Option<Duration>,        // The run_once that first saw the latest command
+    pub tripped:   Option<(Duration, i16)>, // When it tripped, and the command at the time
+}
+
+impl Motor {
+    // Rotor turns per output shaft turn, falling back to the datasheet value if the motor hasn't been initialized
+    pub fn ratio(&self) -> f64 {
+        match self.gear_ratio {
+            r if r > 0.0 => r,
+            _ => gear_ratio(self.motor_type),
+        }
+    }
+
+    // Encoder counts since the zero point, including full turns
+    fn multi_turn(&self) -> i64 {
+        self.turns*COUNTS_PER_REV + self.feedback.position as i64 - self.zero
+    }
+
+    // Multi-turn position of the output shaft (rad), None if the motor hasn't reported any feedback yet
+    pub fn output_position(&self) -> Option<f64> {
+        self.received?;
+        Some(self.multi_turn() as f64/COUNTS_PER_REV as f64 *2f64*PI/self.ratio())
+    }
+
+    // Velocity of the output shaft (rad/s), None if the motor hasn't reported any feedback yet
+    pub fn output_velocity(&self) -> Option<f64> {
+        self.received?;
+        Some(self.feedback.velocity as f64/RPM_PER_ANGULAR/self.ratio())
+    }
+
+    // Measured current (A), 0 for the M2006 which doesn't report it
+    pub fn output_current(&self) -> f64 {
+        match self.motor_type {
+            MotorType::M2006 => 0.0,
+            _ => self.feedback.current as f64/i_cmd_max(self.motor_type)*i_max(self.motor_type),
+        }
+    }
+
+    // A feedback field in the units of get_state, 0 before any feedback has arrived
+    pub fn state(&self, field: FbField) -> f64 {
+        match field {
+            FbField::Position    => self.feedback.position as f64/POS_MAX as f64 *2f64*PI,
+            FbField::Velocity    => self.output_velocity().unwrap_or(0.0),
+            FbField::Current     => self.output_current(),
+            FbField::Temperature => self.feedback.temperature as f64,
+            FbField::MultiTurn   => self.output_position().unwrap_or(0.0),
+        }
+    }
+
+    // state, refusing the fields the motor doesn't report
+    pub fn get_state(&self, id: u8, field: FbField) -> Result<f64, RmError> {
+        if self.motor_type == MotorType::M2006 && (field == FbField::Current || field == FbField::Temperature){
+            return Err(RmError::UnsupportedField { id, motor_type: self.motor_type, field });
+        }
+        Ok(self.state(field))
+    }
+
+    // Configure the motor, resetting its controllers and any move
+    pub fn init(&mut self, motor_type: MotorType, mode: CmdMode, gear_ratio: f64) {
+        self.motor_type  = motor_type;
+        self.mode        = mode;
+        self.gear_ratio  = gear_ratio;
+        self.cascade     = Cascade::new(motor_type, gear_ratio);
+        self.active_move = None;
+        self.watchdog.tripped   = None;
+        self.watchdog.refreshed = true;
+        // Hold the current position rather than driving to 0, or the first feedback's if there hasn't been any yet
+        self.setpoint = match mode {
+            CmdMode::Position => self.output_position().unwrap_or(0.0),
+            _ => 0.0,
+        };
+        self.hold = mode == CmdMode::Position && self.received.is_none();
+    }
+
+    // Advance the motor's clock, see record
+    pub fn tick(&mut self, now: Duration) {
+        self.clock = self.clock.max(now);
+    }
+
+    // Start a run_once at now, see FaultLog
+    pub fn begin_cycle(&mut self, now: Duration) {
+        self.tick(now);
+        self.faults.next_cycle();
+    }
+
+    // Record a fault at the motor's clock, returning whether it has just started (see FaultLog)
+    pub fn record(&mut self, fault: Fault, value: f64) -> bool {
+        self.faults.record(fault, self.clock.as_secs_f64(), value)
+    }
+
+    // Store a received feedback frame, counting encoder rollovers
+    pub fn apply_feedback(&mut self, feedback: Feedback, received: Duration) {
+        self.tick(received);
+        // Feedback arrives at 1kHz so the motor can't have moved more than half a turn between frames.
+        if self.received.is_some() {
+            let delta: i64 = feedback.position as i64 - self.feedback.position as i64;
+            if delta > COUNTS_PER_REV/2 {
+                self.turns -= 1;
+            }
+            else if delta < -COUNTS_PER_REV/2 {
+                self.turns += 1;
+            }
+        }
+        self.received = Some(received);
+        self.feedback = feedback;
+        if let (true, Some(position)) = (self.hold, self.output_position()) {
+            self.setpoint = position;
+            self.hold = false;
+        }
+        // Follow the temperature up at once, but only back down once it has fallen by the hysteresis, so the limit doesn't chatter
+        let temperature: u8 = feedback.temperature;
+        self.thermal = temperature.max(self.thermal.min(temperature.saturating_add(TEMP_HYSTERESIS)));
+    }
+
+    /*
+    **  Check the temperature, run every run_once before the controllers
+    **  Records that the limit is lowered (see sent_command), and at the maximum writes 0 command and returns error.
+    */
+    pub fn run_thermal(&mut self, id: u8) -> Result<(), RmError> {
+        let temperature: f64 = self.feedback.temperature as f64;
+        match self.derating() {
+            0.0 => {self.record(Fault::OverTemperature, temperature);},
+            d if d < 1.0 => {self.record(Fault::Derated, temperature);},
+            _ => (),
+        }
+        self.check_temperature(id)
+    }
+
+    // At the maximum temperature, write 0 command and return error. Checked by every command, and recorded by run_thermal.
+    fn check_temperature(&mut self, id: u8) -> Result<(), RmError> {
+        if self.derating() > 0.0 {
+            return Ok(());
+        }
+        self.command = 0;
+        Err(RmError::OverTemperature { id, temperature: self.feedback.temperature as u16, limit: self.temp_limits().1 })
+    }
+
+    /*
+    **  The command run_once sends: command limited to the derated fraction of the motor's voltage or current limit
+    **  Applied as it is sent rather than to command, so an open-loop command follows the temperature down and back up
+    **  without being set again, until it is cut off at the maximum: check_temperature zeroes it, so it then needs a new command.
+    */
+    pub fn sent_command(&self) -> i16 {
+        let full: f64 = match drive(self.mode, self.motor_type) {
+            CmdMode::Voltage => V_CMD_MAX,
+            _ => i_cmd_max(self.motor_type),
+        };
+        let limit: f64 = full*self.derating();
+        (self.command as f64).clamp(-limit, limit) as i16
+    }
+
+    // Temperatures derating starts and ends at (C)
+    pub fn temp_limits(&self) -> (u8, u8) {
+        self.temp_limits.unwrap_or((TEMP_DERATE, TEMP_MAX))
+    }
+
+    // See set_temperature_limits
+    pub fn set_temperature_limits(&mut self, derate: u8, max: u8) -> Result<(), RmError> {
+        if derate >= max {
+            return Err(RmError::InvalidParameter { name: "derate", value: derate as f64 });
+        }
+        self.temp_limits = Some((derate, max));
+        Ok(())
+    }
+
+    /*
+    **  Fraction of the voltage or current limit available at the motor's temperature
+    **  1 up to TEMP_DERATE, falling linearly to 0 at TEMP_MAX (or the limits from set_temperature_limits)
+    */
+    pub fn derating(&self) -> f64 {
+        let (start, max): (u8, u8) = self.temp_limits();
+        match self.thermal {
+            t if t <= start => 1.0,
+            t if t >= max => 0.0,
+            t => (max - t) as f64/(max - start) as f64,
+        }
+    }
+
+    // Time since the latest feedback of a motor that should be reporting it, StaleFeedback if it never has
+    pub fn feedback_age(&self, id: u8, now: Duration) -> Result<Duration, RmError> {
+        Ok(now.saturating_sub(self.received.ok_or(RmError::StaleFeedback { id, age: None })?))
+    }
+
+    // Make the current position the zero point of FbField::MultiTurn
+    pub fn zero_position(&mut self, id: u8) -> Result<(), RmError> {
+        if self.received.is_none() {
+            return Err(RmError::StaleFeedback { id, age: None });
+        }
+        self.zero = self.turns*COUNTS_PER_REV + self.feedback.position as i64;
+        Ok(())
+    }
+
+    // See set_cmd
+    pub fn set_cmd(&mut self, id: u8, cmd: f64) -> Result<(), RmError> {
+        // Check id range
+        if id>id_max(self.motor_type) { return Err(RmError::IdOutOfRange { id, min: ID_MIN, max: id_max(self.motor_type) }); }
+        if !cmd.is_finite() {
+            return Err(RmError::InvalidParameter { name: "cmd", value: cmd });
+        }
+        let mut mode: CmdMode = self.mode;
+        let motor_type: MotorType = self.motor_type;
+        if mode == CmdMode::Disabled {
+            self.record(Fault::UnsupportedMode, mode as i32 as f64);
+            return Err(RmError::UnsupportedMode { id, motor_type, mode });
+        }
+        self.watchdog.refreshed = true;
+        if mode == CmdMode::Position || mode == CmdMode::Velocity {
+            // Takes over from any move_to
+            self.active_move = None;
+            self.setpoint = cmd;
+            self.hold = false;
+            return Ok(());
+        }
+        let mut cmd_actual: f64 = cmd;
+        // Convert output shaft torque commands to corresponding current commands
+        // nm_per_a is given at the output of the stock gearbox
+        if mode == CmdMode::Torque {
+            mode = CmdMode::Current;
+            cmd_actual/=nm_per_a(motor_type)/gear_ratio(motor_type)*self.ratio();
+        }
+        self.write_cmd(id, mode, cmd_actual)
+    }
+
+    // See set_stage_gains
+    pub fn set_stage_gains(&mut self, id: u8, stage: Stage, gains: PidGains) -> Result<(), RmError> {
+        let PidGains { kp, ki, kd, kf } = gains;
+        for (name, value) in [("kp", kp), ("ki", ki), ("kd", kd), ("kf", kf)] {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(RmError::InvalidParameter { name, value });
+            }
+        }
+        // The Current stage can only pass its setpoint through if there is no current feedback
+        if stage == Stage::Current && self.motor_type == MotorType::M2006 && !gains.passes_through() {
+            return Err(RmError::UnsupportedField { id, motor_type: self.motor_type, field: FbField::Current });
+        }
+        self.cascade.set_gains(self.motor_type, stage, gains);
+        Ok(())
+    }
+
+    // See set_stage_limit
+    pub fn set_stage_limit(&mut self, stage: Stage, limit: f64) -> Result<(), RmError> {
+        if limit.is_nan() || limit <= 0.0 {
+            return Err(RmError::InvalidParameter { name: "limit", value: limit });
+        }
+        self.cascade.limits[stage as usize] = limit;
+        Ok(())
+    }
+
+    // See set_feedforward
+    pub fn set_feedforward(&mut self, stage: Stage, value: f64) -> Result<(), RmError> {
+        if !value.is_finite() {
+            return Err(RmError::InvalidParameter { name: "feedforward", value });
+        }
+        self.cascade.feedforward[stage as usize] = value;
+        Ok(())
+    }
+
+    pub fn stage_signal(&self, stage: Stage, signal: StageSignal) -> f64 {
+        self.cascade.stages[stage as usize].signal(signal)
+    }
+
+    // See move_to_s_curve
+    pub fn move_to(&mut self, id: u8, target: f64, max_vel: f64, max_accel: f64, max_jerk: f64, now: Duration) -> Result<(), RmError> {
+        for (name, value) in [("target", target), ("max_jerk", max_jerk)] {
+            if !value.is_finite() {
+                return Err(RmError::InvalidParameter { name, value });
+            }
+        }
+        for (name, value) in [("max_vel", max_vel), ("max_accel", max_accel)] {
+            if !(value.is_finite() && value > 0.0) {
+                return Err(RmError::InvalidParameter { name, value });
+            }
+        }
+        self.tick(now);
+        if self.mode != CmdMode::Position {
+            self.record(Fault::UnsupportedMode, self.mode as i32 as f64);
+            return Err(RmError::UnsupportedMode { id, motor_type: self.motor_type, mode: self.mode });
+        }
+        self.watchdog.refreshed = true;
+        self.hold = false;
+        let (position, velocity): (f64, f64) = match &self.active_move {
+            Some(m) => m.sample(now),
+            None => (self.setpoint, 0.0),
+        };
+        self.active_move = Some(Move {
+            trajectory: Trajectory::new(position, velocity, target, max_vel, max_accel, max_jerk),
+            start: now,
+            max_accel,
+            max_jerk,
+            cancelled: false,
+        });
+        Ok(())
+    }
+
+    pub fn move_status(&self, now: Duration) -> MoveStatus {
+        match &self.active_move {
+            Some(m) => m.status(now),
+            None => MoveStatus::Idle,
+        }
+    }
+
+    // See clear_faults
+    pub fn clear_faults(&mut self) {
+        self.faults.clear();
+    }
+
+    // See cancel_move
+    pub fn cancel_move(&mut self, now: Duration) {
+        self.tick(now);
+        if let Some(m) = &mut self.active_move {
+            if !m.cancelled {
+                let (position, velocity): (f64, f64) = m.sample(now);
+                m.trajectory = Trajectory::stop(position, velocity, m.max_accel, m.max_jerk);
+                m.start = now;
+                m.cancelled = true;
+            }
+        }
+    }
+
+    /*
+    **  Start ramping the motor down for cleanup. Avoid get_state - simplify by using only voltage and current commands.
+    **  Switches to the underlying mode so set_cmd takes volts or amps and run_once doesn't run any controllers
+    **  Returns the latest command in V or A, None if the motor is disabled
+    */
+    #[cfg(feature = "std")]
+    pub fn begin_ramp_down(&mut self) -> Option<f64> {
+        self.mode = drive(self.mode, self.motor_type);
+        match self.mode {
+            CmdMode::Voltage  => Some(self.command as f64 /V_CMD_MAX*V_MAX),
+            CmdMode::Current  => Some(self.command as f64 /i_cmd_max(self.motor_type)*i_max(self.motor_type)),
+            _ => None,
+        }
+    }
+
+    // Whether run_controller has anything to do
+    pub fn needs_controller(&self) -> bool {
+        matches!(self.mode, CmdMode::Position | CmdMode::Velocity) && self.received.is_some() && self.watchdog.tripped.is_none() && !self.link_stopped()
+    }
+
+    // Forget any target and controller state, leaving a Position mode motor holding where it is and any other stopped
+    pub fn stop(&mut self) {
+        self.command     = 0;
+        self.active_move = None;
+        self.cascade.reset();
+        self.setpoint = match self.mode {
+            CmdMode::Position => self.output_position().unwrap_or(self.setpoint),
+            _ => 0.0,
+        };
+    }
+
+    pub fn link_status(&self, now: Duration) -> LinkStatus {
+        match self.received.map(|received| now.saturating_sub(received)) {
+            None => LinkStatus::NeverSeen,
+            Some(age) if age >= self.link.timeout => LinkStatus::Lost,
+            Some(age) if age >= STALE_AGE.min(self.link.timeout) => LinkStatus::Stale,
+            Some(_) => LinkStatus::Ok,
+        }
+    }
+
+    // See set_link_policy
+    pub fn set_link_policy(&mut self, policy: LinkPolicy, timeout_ms: u64) -> Result<(), RmError> {
+        if timeout_ms == 0 {
+            return Err(RmError::InvalidParameter { name: "timeout_ms", value: 0.0 });
+        }
+        self.link.policy  = policy;
+        self.link.timeout = Duration::from_millis(timeout_ms);
+        Ok(())
+    }
+
+    // The link policy is holding the motor's command at 0
+    pub fn link_stopped(&self) -> bool {
+        self.link.latched || (self.link.lost && self.link.policy != LinkPolicy::Warn)
+    }
+
+    // A lost link has faulted the whole bus, see LinkPolicy::FaultBus
+    pub fn faults_bus(&self) -> bool {
+        self.link.latched && self.link.policy == LinkPolicy::FaultBus
+    }
+
+    /*
+    **  Apply the link policy, run every run_once for a motor that has sent feedback before the watchdog and controllers
+    **  Returns StaleFeedback while the link is lost and the policy stops the motor, and LinkFault once it is back until clear_link_fault.
+    */
+    pub fn run_link(&mut self, id: u8, now: Duration) -> Result<(), RmError> {
+        self.tick(now);
+        let age: Duration = match self.received {
+            Some(received) => now.saturating_sub(received),
+            None => return Ok(()), // Reported by run_once before this
+        };
+        let lost: bool = age >= self.link.timeout;
+        if lost != self.link.lost {
+            self.notice = Some(match lost {
+                true  => Notice::LinkLost { motor_type: self.motor_type, id, timeout: self.link.timeout },
+                false => Notice::LinkBack { motor_type: self.motor_type, id },
+            });
+            self.link.lost = lost;
+            // Also when it comes back, so a Position mode motor holds where it is now rather than where it was lost
+            if self.link.policy != LinkPolicy::Warn {
+                self.stop();
+            }
+            if lost {
+                self.record(Fault::StaleFeedback, age.as_secs_f64()*1e3);
+                self.link.latched = matches!(self.link.policy, LinkPolicy::Disable | LinkPolicy::FaultBus);
+                if self.link.latched {
+                    self.record(Fault::LinkFault, self.link.timeout.as_secs_f64()*1e3);
+                }
+            }
+        }
+        if self.link_stopped() {
+            self.command = 0; // In case of a set_cmd in the meantime
+        }
+        match (lost, self.link_stopped()) {
+            (true, true) => Err(RmError::StaleFeedback { id, age: Some(age) }),
+            (false, true) => Err(RmError::LinkFault { id }),
+            (_, false) => Ok(()),
+        }
+    }
+
+    // See clear_link_fault
+    pub fn clear_link_fault(&mut self, id: u8, now: Duration) -> Result<(), RmError> {
+        if self.link_status(now) == LinkStatus::Lost || self.received.is_none() {
+            return Err(RmError::StaleFeedback { id, age: self.received.map(|received| now.saturating_sub(received)) });
+        }
+        self.link.latched = false;
+        Ok(())
+    }
+
+    // See set_command_timeout
+    pub fn set_command_timeout(&mut self, timeout_ms: u64, ramp_ms: u64) {
+        self.watchdog.timeout   = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms));
+        self.watchdog.ramp      = Duration::from_millis(ramp_ms);
+        self.watchdog.tripped   = None;
+        // Counts from the next run_once rather than from the last set_cmd, however long ago that was
+        self.watchdog.refreshed = true;
+    }
+
+    /*
+    **  Check the command watchdog, run every run_once before the controllers
+    **  Once no command has been given for the timeout, take over the command: ramp it to 0 and keep it there, without running the controllers,
+    **  until the next set_cmd or move_to. A move_to counts as a command until it finishes. Returns CommandTimeout while it is tripped.
+    */
+    pub fn run_watchdog(&mut self, id: u8, now: Duration) -> Result<(), RmError> {
+        let moving: bool = self.move_status(now) == MoveStatus::Moving;
+        let watchdog: &mut Watchdog = &mut self.watchdog;
+        if core::mem::take(&mut watchdog.refreshed) || moving {
+            watchdog.last_cmd = Some(now);
+            watchdog.tripped  = None;
+        }
+        let timeout: Duration = match watchdog.timeout {
+            Some(timeout) if self.mode != CmdMode::Disabled => timeout,
+            _ => return Ok(()),
+        };
+        let age: Duration = now.saturating_sub(*watchdog.last_cmd.get_or_insert(now));
+        if age < timeout {
+            return Ok(());
+        }
+        if watchdog.tripped.is_none() {
+            self.record(Fault::CommandTimeout, age.as_secs_f64()*1e3);
+        }
+        let (tripped, from): (Duration, i16) = *self.watchdog.tripped.get_or_insert((now, self.command));
+        let remaining: f64 = match self.watchdog.ramp {
+            ramp if ramp.is_zero() => 0.0,
+            ramp => 1.0 - (now.saturating_sub(tripped).as_secs_f64()/ramp.as_secs_f64()).min(1.0),
+        };
+        // Start over from the new command once there is one, rather than winding up while stopped or driving back to an old target
+        self.stop();
+        self.command = (from as f64*remaining) as i16;
+        Err(RmError::CommandTimeout { id, age })
+    }
+
+    // Run the cascade of a motor in Position or Velocity mode
+    pub fn run_controller(&mut self, id: u8, now: Duration) -> Result<(), RmError> {
+        let first: Stage = match self.mode {
+            CmdMode::Position => Stage::Position,
+            CmdMode::Velocity => Stage::Velocity,
+            _ => return Ok(()),
+        };
+        let measurements: [f64; 3] = match (self.output_position(), self.output_velocity()) {
+            (Some(position), Some(velocity)) => [position, velocity, self.output_current()],
+            _ => return Ok(()),
+        };
+        // Follow the trajectory of a move_to, feeding its velocity forward to the Velocity stage
+        let mut feedforward: [f64; 3] = [0.0; 3];
+        if let Some(m) = &self.active_move {
+            let (position, velocity): (f64, f64) = m.sample(now);
+            self.setpoint = position;
+            feedforward[Stage::Velocity as usize] = velocity;
+        }
+        // Derate the controllers' limits along with what is sent, so they don't wind up while the motor is derated
+        let output: f64 = self.cascade.update(first, self.setpoint, feedforward, measurements, self.derating(), now);
+        let result: Result<(), RmError> = self.write_cmd(id, drive(self.mode, self.motor_type), output);
+        if result.is_err() {
+            self.cascade.reset();
+        }
+        result
+    }
+
+    /*
+    **  Store a voltage or current command for the next run_once, enforcing the motor's limits
+    **  drive: CmdMode::Voltage or CmdMode::Current
+    */
+    fn write_cmd(&mut self, id: u8, drive: CmdMode, cmd: f64) -> Result<(), RmError> {
+        let motor_type: MotorType = self.motor_type;
+        self.check_temperature(id)?;
+        let mut cmd_actual: f64 = cmd;
+        // Limit to max allowable command values
+        // Only warned about when it starts, since a controller can ask for too much every run_once
+        if drive == CmdMode::Voltage && cmd_actual.abs() > V_MAX {
+            if self.record(Fault::Clamped, cmd) {
+                self.notice = Some(Notice::Clamped { drive, max: V_MAX, cmd });
+            }
+            cmd_actual = V_MAX*cmd.abs()/cmd;
+        }
+        let i_max: f64 = i_max(motor_type);
+
+        if drive == CmdMode::Current && cmd_actual.abs() > i_max {
+            if self.record(Fault::Clamped, cmd) {
+                self.notice = Some(Notice::Clamped { drive, max: i_max, cmd });
+            }
+            cmd_actual = i_max*cmd.abs()/cmd;
+        }
+
+        self.command = match drive {
+            CmdMode::Voltage => (V_CMD_MAX*cmd_actual/V_MAX) as i16,
+            CmdMode::Current => (i_cmd_max(motor_type)*cmd_actual/i_max) as i16,
+            _ => {
+                self.record(Fault::UnsupportedMode, drive as i32 as f64);
+                return Err(RmError::UnsupportedMode { id, motor_type, mode: drive });
+            },
+        };
+        Ok(())
+    }
+}
+
+/*
+**  Check a motor can be initialized alongside the others on the bus, returning its index
+**  other: the type and mode of the motor at an index
+*/
+pub(crate) fn check_init(id: u8, motor_type: MotorType, mode: CmdMode, gear_ratio: f64, other: impl Fn(usize) -> (MotorType, CmdMode)) -> Result<usize, RmError> {
+    if !(gear_ratio.is_finite() && gear_ratio > 0.0) {
+        return Err(RmError::InvalidParameter { name: "gear_ratio", value: gear_ratio });
+    }
+    if id < ID_MIN || id > id_max(motor_type) {
+        return Err(RmError::IdOutOfRange { id, min: ID_MIN, max: id_max(motor_type) });
+    }
+    // M3508 and M2006 only accept current commands, which the Torque, Velocity and Position modes are built on
+    if (motor_type == MotorType::M3508 || motor_type == MotorType::M2006) && mode == CmdMode::Voltage {
+        return Err(RmError::UnsupportedMode { id, motor_type, mode });
+    }
+    let idx: usize = index(motor_type.family(), id)?;
+
+    // Check for ID collisions - this is a limitation of DJI's address scheme. Motors of different families
+    // can share a feedback ID (GM6020 1-4 and M3508/M2006 5-8), and then the slot of a command frame as well.
+    let fb_id: u16 = protocol::feedback_id(motor_type, id);
+    let slot: Option<(u16, usize)> = protocol::command_slot(motor_type, drive(mode, motor_type), id);
+    for i in 0 .. ARR_LEN {
+        let (other_type, other_mode): (MotorType, CmdMode) = other(i);
+        // The motor being initialized is replaced rather than collided with
+        if i == idx || other_mode == CmdMode::Disabled {continue;}
+        let other_id: u8 = self::id(i);
+        if protocol::feedback_id(other_type, other_id) == fb_id || protocol::command_slot(other_type, drive(other_mode, other_type), other_id) == slot {
+            return Err(RmError::IdCollision { motor_type, id, other: other_type, other_id });
+        }
+    }
+    Ok(idx)
+}
+
+/*
+**  Table index and ID of the motor a method acts on
+**  mode: the mode of the motor at an index
+**  A plain ID goes to the family with a motor initialized with it, or the first family it is in range for if neither has one.
+*/
+pub(crate) fn resolve(motor: MotorId, mode: impl Fn(usize) -> CmdMode) -> Result<(usize, u8), RmError> {
+    let id: u8 = match motor {
+        MotorId::Typed(motor_type, id) => return Ok((index(motor_type.family(), id)?, id)),
+        MotorId::Id(id) => id,
+    };
+    let candidates: [Option<usize>; 2] = [Family::GM6020, Family::C6x0].map(|family| index(family, id).ok());
+    let mut enabled = candidates.iter().flatten().filter(|&&i| mode(i) != CmdMode::Disabled);
+    match (enabled.next(), enabled.next()) {
+        (Some(_), Some(_)) => Err(RmError::AmbiguousId { id }),
+        (Some(&i), None) => Ok((i, id)),
+        _ => match candidates.into_iter().flatten().next() {
+            Some(i) => Ok((i, id)),
+            None => Err(RmError::IdOutOfRange { id, min: ID_MIN, max: FAMILY_LEN as u8 }),
+        },
+    }
+}
+
+/*
+**  Table index of the motor that sent a feedback frame, None if it isn't a feedback frame
+**  0x205-0x208 is both GM6020 ID 1-4 and M3508/M2006 ID 5-8, which check_init doesn't let both be initialized:
+**  it is parsed as the M3508/M2006 if that is, otherwise as the GM6020.
+**  mode: the mode of the motor at an index
+*/
+pub(crate) fn feedback_index(frame_id: u16, mode: impl Fn(usize) -> CmdMode) -> Option<usize> {
+    let upper_3508: bool = match protocol::feedback_motor(frame_id, true)? {
+        (Family::C6x0, id) => index(Family::C6x0, id).is_ok_and(|i| mode(i) != CmdMode::Disabled),
+        (Family::GM6020, _) => false,
+    };
+    let (family, id): (Family, u8) = protocol::feedback_motor(frame_id, upper_3508)?;
+    index(family, id).ok()
+}
+
+// Convert a family and ID to a table index, checking the ID is in range for the family first
+pub(crate) fn index(family: Family, id: u8) -> Result<usize, RmError> {
+    let max: u8 = id_max(first_type(family));
+    if id < ID_MIN || id > max {
+        return Err(RmError::IdOutOfRange { id, min: ID_MIN, max });
+    }
+    Ok(family as usize*FAMILY_LEN + (id - ID_MIN) as usize)
+}
+
+// ID of the motor at a table index
+pub(crate) fn id(idx: usize) -> u8 {
+    (idx % FAMILY_LEN) as u8 + ID_MIN
+}
+
+// The motor at a table index before it is initialized, disabled and of the first type of its family
+pub(crate) fn blank(idx: usize) -> Motor {
+    let family: Family = if idx < FAMILY_LEN { Family::GM6020 } else { Family::C6x0 };
+    Motor { motor_type: first_type(family), ..Default::default() }
+}
+
+fn first_type(family: Family) -> MotorType {
+    match family {
+        Family::GM6020 => MotorType::GM6020,
+        Family::C6x0   => MotorType::M3508,
+    }
+}
+
+const MAX_GROUPS: usize = 6; // At most one frame for each command ID
+
+// The command frames run_once sends: frame ID and the commands of the four motors it addresses
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct CommandGroups {
+    groups: [(u16, [i16; 4]); MAX_GROUPS],
+    len:    usize,
+}
+
+impl CommandGroups {
+    /*
+    **  Gather the commands of the motors into the frames they are sent in, only including the frames some motor needs
+    **  motor: the type, mode and command of the motor at an index
+    */
+    pub fn new(motor: impl Fn(usize) -> (MotorType, CmdMode, i16)) -> CommandGroups {
+        let mut groups: CommandGroups = CommandGroups::default();
+        for i in 0 .. ARR_LEN {
+            let (motor_type, mode, command): (MotorType, CmdMode, i16) = motor(i);
+            let (frame_id, slot): (u16, usize) = match protocol::command_slot(motor_type, drive(mode, motor_type), id(i)) {
+                Some(slot) => slot,
+                None => continue, // Disabled
+            };
+            match groups.groups[.. groups.len].iter_mut().find(|(id, _)| *id == frame_id) {
+                Some((_, commands)) => commands[slot] = command,
+                None => {
+                    groups.groups[groups.len].0 = frame_id;
+                    groups.groups[groups.len].1[slot] = command;
+                    groups.len += 1;
+                },
+            }
+        }
+        groups.groups[.. groups.len].sort_unstable_by_key(|(frame_id, _)| *frame_id);
+        groups
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(u16, [i16; 4])> {
+        self.groups[.. self.len].iter()
+    }
+}
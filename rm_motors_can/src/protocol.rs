@@ -0,0 +1,144 @@
+use crate::{id_max, CmdMode, Family, MotorType, ID_MIN};
+use embedded_can::{Frame, Id, StandardId};
+
+//////
+// The CAN protocol of the RoboMaster motors, with no I/O or state: which frame each motor is commanded in and
+// reports feedback with, and how the values are packed into the 8 data bytes. All values are big-endian.
+// Used by RmMotorsCan, the simulator and the async API, and available for tools that talk to the motors directly.
+//////
+
+pub const FB_ID_BASE_6020: u16 = 0x204;
+pub const FB_ID_BASE_3508: u16 = 0x200;
+pub const CMD_ID_V_L_6020: u16 = 0x1ff;
+pub const CMD_ID_V_H_6020: u16 = 0x2ff;
+pub const CMD_ID_I_L_6020: u16 = 0x1fe;
+pub const CMD_ID_I_H_6020: u16 = 0x2fe;
+pub const CMD_ID_I_L_3508: u16 = 0x200;
+pub const CMD_ID_I_H_3508: u16 = CMD_ID_V_L_6020;
+pub const CMD_ID_I_L_2006: u16 = CMD_ID_I_L_3508;
+pub const CMD_ID_I_H_2006: u16 = CMD_ID_I_H_3508;
+// Every frame a command can be sent in, once each
+pub const CMD_IDS: [u16; 5] = [CMD_ID_V_L_6020, CMD_ID_V_H_6020, CMD_ID_I_L_6020, CMD_ID_I_H_6020, CMD_ID_I_L_3508];
+
+// Raw values of one feedback frame
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Feedback {
+    pub position:    u16, // [0, 8191]
+    pub velocity:    i16, // rpm
+    pub current:     i16, // [-i_cmd_max, i_cmd_max]:[-i_max, i_max], not reported by the M2006
+    pub temperature: u8,  // C, not reported by the M2006
+}
+
+impl Feedback {
+    // Parse the data of a feedback frame, None if it is too short
+    pub fn decode(data: &[u8]) -> Option<Feedback> {
+        if data.len() < 7 {
+            return None;
+        }
+        Some(Feedback {
+            position:    u16::from_be_bytes([data[0], data[1]]),
+            velocity:    i16::from_be_bytes([data[2], data[3]]),
+            current:     i16::from_be_bytes([data[4], data[5]]),
+            temperature: data[6],
+        })
+    }
+
+    // Data of the feedback frame a motor would send. The last byte is unused.
+    pub fn encode(&self) -> [u8; 8] {
+        let [p0, p1] = self.position.to_be_bytes();
+        let [v0, v1] = self.velocity.to_be_bytes();
+        let [c0, c1] = self.current.to_be_bytes();
+        [p0, p1, v0, v1, c0, c1, self.temperature, 0]
+    }
+}
+
+/*
+**  Where a motor's command goes: the ID of the frame and the index of its value in the frame
+**  drive: CmdMode::Voltage or CmdMode::Current, the command actually sent to the motor
+**  returns: None if the motor can't be commanded that way or the ID is out of range
+*/
+pub fn command_slot(motor_type: MotorType, drive: CmdMode, id: u8) -> Option<(u16, usize)> {
+    if id < ID_MIN || id > id_max(motor_type) {
+        return None;
+    }
+    let low: bool = id < 5;
+    let frame_id: u16 = match (motor_type, drive) {
+        (MotorType::GM6020, CmdMode::Voltage) => if low { CMD_ID_V_L_6020 } else { CMD_ID_V_H_6020 },
+        (MotorType::GM6020, CmdMode::Current) => if low { CMD_ID_I_L_6020 } else { CMD_ID_I_H_6020 },
+        (MotorType::M3508,  CmdMode::Current) => if low { CMD_ID_I_L_3508 } else { CMD_ID_I_H_3508 },
+        (MotorType::M2006,  CmdMode::Current) => if low { CMD_ID_I_L_2006 } else { CMD_ID_I_H_2006 },
+        (_, _) => return None,
+    };
+    Some((frame_id, ((id - ID_MIN) % 4) as usize))
+}
+
+// Command frame of any CAN driver, None if frame_id isn't a standard ID
+pub fn command_frame<F: Frame>(frame_id: u16, commands: [i16; 4]) -> Option<F> {
+    F::new(StandardId::new(frame_id)?, &encode_command(commands))
+}
+
+// Data of a command frame, one value for each of the four motors it addresses
+pub fn encode_command(commands: [i16; 4]) -> [u8; 8] {
+    let mut data: [u8; 8] = [0; 8];
+    for (bytes, command) in data.chunks_exact_mut(2).zip(commands) {
+        bytes.copy_from_slice(&command.to_be_bytes());
+    }
+    data
+}
+
+// Parse the data of a command frame, None if it is too short
+pub fn decode_command(data: &[u8]) -> Option<[i16; 4]> {
+    if data.len() < 8 {
+        return None;
+    }
+    Some(core::array::from_fn(|i| i16::from_be_bytes([data[2*i], data[2*i + 1]])))
+}
+
+// Frames that another controller already driving the bus would be sending
+pub fn is_command_id(frame_id: u16) -> bool {
+    CMD_IDS.contains(&frame_id)
+}
+
+// ID of the frames a motor sends its feedback in
+pub fn feedback_id(motor_type: MotorType, id: u8) -> u16 {
+    match motor_type {
+        MotorType::GM6020 => FB_ID_BASE_6020 + id as u16,
+        MotorType::M3508 | MotorType::M2006 => FB_ID_BASE_3508 + id as u16,
+    }
+}
+
+/*
+**  Family and ID of the motor that sent a feedback frame, None if it isn't a feedback frame
+**  upper_3508: 0x205-0x208 is both GM6020 ID 1-4 and M3508/M2006 ID 5-8, true to parse it as the latter
+*/
+pub fn feedback_motor(frame_id: u16, upper_3508: bool) -> Option<(Family, u8)> {
+    match frame_id {
+        0x201 ..= 0x204 => Some((Family::C6x0, (frame_id - FB_ID_BASE_3508) as u8)),
+        0x205 ..= 0x208 if upper_3508 => Some((Family::C6x0, (frame_id - FB_ID_BASE_3508) as u8)),
+        0x205 ..= 0x20b => Some((Family::GM6020, (frame_id - FB_ID_BASE_6020) as u8)),
+        _ => None,
+    }
+}
+
+// feedback_motor without the family
+pub fn feedback_motor_id(frame_id: u16, upper_3508: bool) -> Option<u8> {
+    Some(feedback_motor(frame_id, upper_3508)?.1)
+}
+
+// Parse a feedback frame into the ID of the motor that sent it and its values, None if it isn't a valid feedback frame
+pub fn decode_feedback(frame_id: u16, data: &[u8], upper_3508: bool) -> Option<(u8, Feedback)> {
+    Some((feedback_motor_id(frame_id, upper_3508)?, Feedback::decode(data)?))
+}
+
+// decode_feedback for a frame of any CAN driver. Remote and extended frames aren't feedback.
+pub fn decode_feedback_frame<F: Frame>(frame: &F, upper_3508: bool) -> Option<(u8, Feedback)> {
+    decode_feedback(data_frame_id(frame)?, frame.data(), upper_3508)
+}
+
+// ID of a standard data frame of any CAN driver, the only kind the motors send. None for remote and extended frames.
+pub fn data_frame_id<F: Frame>(frame: &F) -> Option<u16> {
+    match frame.id() {
+        Id::Standard(id) if frame.is_data_frame() => Some(id.as_raw()),
+        _ => None,
+    }
+}
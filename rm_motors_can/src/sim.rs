@@ -0,0 +1,261 @@
+use crate::transport::filters_accept;
+use crate::protocol::{self, Feedback};
+use crate::{gear_ratio, i_cmd_max, i_max, lock, nm_per_a, CanTransport, CmdMode, MotorType, RmError, ID_MIN, id_max};
+use crate::{POS_MAX, RPM_PER_ANGULAR, RPM_PER_V, V_CMD_MAX, V_MAX};
+use embedded_can::{Frame as EmbeddedFrame, StandardId};
+use socketcan::{CanFilter, CanFrame, Frame};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::io;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TICK: Duration = Duration::from_millis(1); // Real motors send feedback at 1kHz
+const SUBSTEPS: u32 = 10;                         // Physics steps per tick, keeps explicit integration stable for the stiffest model (GM6020)
+const MAX_CATCH_UP: u32 = 1000;                   // Don't simulate more than 1s at once if nobody has read from the bus for a while
+const MAX_PENDING: usize = 1024;                  // Like a socket receive buffer, old frames are dropped when nobody reads them
+
+/*
+**  Physical parameters of a simulated motor. Everything is referred to the shaft whose position and speed
+**  the motor reports, which is the rotor (before the gearbox) for M3508 and M2006.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct MotorModel {
+    pub resistance:          f64, // Ohm
+    pub kt:                  f64, // N*m/A
+    pub ke:                  f64, // V/(rad/s) back-EMF
+    pub inertia:             f64, // kg*m^2 rotor plus load
+    pub viscous:             f64, // N*m/(rad/s)
+    pub coulomb:             f64, // N*m
+    pub thermal_resistance:  f64, // C/W winding to ambient
+    pub thermal_capacitance: f64, // J/C
+    pub ambient:             f64, // C
+}
+
+impl MotorModel {
+    // Rough values from the datasheets with a small load attached
+    pub fn for_type(motor_type: MotorType) -> MotorModel {
+        match motor_type {
+            MotorType::GM6020 => MotorModel {
+                resistance: 1.8, kt: nm_per_a(motor_type), ke: RPM_PER_ANGULAR/RPM_PER_V,
+                inertia: 5.0e-4, viscous: 1.0e-4, coulomb: 0.01,
+                thermal_resistance: 2.0, thermal_capacitance: 200.0, ambient: 25.0,
+            },
+            // No-load speed 482rpm at the output
+            MotorType::M3508 => MotorModel {
+                resistance: 0.194, kt: nm_per_a(motor_type)/gear_ratio(motor_type), ke: V_MAX/(482.0*gear_ratio(motor_type)/RPM_PER_ANGULAR),
+                inertia: 5.0e-5, viscous: 1.0e-6, coulomb: 2.0e-3,
+                thermal_resistance: 1.2, thermal_capacitance: 250.0, ambient: 25.0,
+            },
+            // No-load speed 500rpm at the output
+            MotorType::M2006 => MotorModel {
+                resistance: 0.5, kt: nm_per_a(motor_type)/gear_ratio(motor_type), ke: V_MAX/(500.0*gear_ratio(motor_type)/RPM_PER_ANGULAR),
+                inertia: 5.0e-6, viscous: 5.0e-7, coulomb: 5.0e-4,
+                thermal_resistance: 2.0, thermal_capacitance: 60.0, ambient: 25.0,
+            },
+        }
+    }
+}
+
+// Which kind of command the motor was last sent. Real GM6020s are configured for one or the other in RoboMaster Assistant.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Drive { Voltage, Current }
+
+struct SimMotor {
+    motor_type:  MotorType,
+    id:          u8,
+    model:       MotorModel,
+    drive:       Option<Drive>, // None until the first command arrives
+    command:     i16,
+    angle:       f64, // rad
+    velocity:    f64, // rad/s
+    current:     f64, // A
+    temperature: f64, // C
+}
+
+impl SimMotor {
+    fn fb_id(&self) -> u16 {
+        protocol::feedback_id(self.motor_type, self.id)
+    }
+
+    // Which command this motor listens to in the given frame, and where in the frame its value is
+    fn cmd_slot(&self, frame_id: u16) -> Option<(Drive, usize)> {
+        [(Drive::Voltage, CmdMode::Voltage), (Drive::Current, CmdMode::Current)].into_iter().find_map(|(drive, mode)| {
+            match protocol::command_slot(self.motor_type, mode, self.id) {
+                Some((id, slot)) if id == frame_id => Some((drive, slot)),
+                _ => None,
+            }
+        })
+    }
+
+    fn step(&mut self, dt: f64) {
+        let m: MotorModel = self.model;
+        let i_limit: f64 = i_max(self.motor_type);
+        // Current the driver can push given the supply voltage and back-EMF
+        let i_avail = |v: f64| (v - m.ke*self.velocity)/m.resistance;
+        self.current = match self.drive {
+            None => 0.0,
+            Some(Drive::Voltage) => i_avail(self.command as f64/V_CMD_MAX*V_MAX).clamp(-i_limit, i_limit),
+            Some(Drive::Current) => (self.command as f64/i_cmd_max(self.motor_type)*i_limit).clamp(i_avail(-V_MAX), i_avail(V_MAX)),
+        };
+
+        let torque: f64 = m.kt*self.current - m.viscous*self.velocity;
+        // Static friction holds the rotor still until the driving torque overcomes it
+        let friction: f64 = if self.velocity != 0.0 { m.coulomb*self.velocity.signum() } else { torque.clamp(-m.coulomb, m.coulomb) };
+        let velocity: f64 = self.velocity + (torque - friction)/m.inertia*dt;
+        self.velocity = if self.velocity != 0.0 && velocity.signum() != self.velocity.signum() && torque.abs() <= m.coulomb { 0.0 } else { velocity };
+        self.angle = (self.angle + self.velocity*dt).rem_euclid(2.0*PI);
+
+        let heat: f64 = self.current*self.current*m.resistance;
+        self.temperature += (heat - (self.temperature - m.ambient)/m.thermal_resistance)/m.thermal_capacitance*dt;
+    }
+
+    fn feedback(&self) -> Option<CanFrame> {
+        let position: u16 = ((self.angle/(2.0*PI)*(POS_MAX as f64 + 1.0)) as u16).min(POS_MAX);
+        let rpm: i16 = (self.velocity*RPM_PER_ANGULAR).round() as i16;
+        let current: i16 = (self.current/i_max(self.motor_type)*i_cmd_max(self.motor_type)).round() as i16;
+        let temperature: u8 = match self.motor_type {
+            MotorType::M2006 => 0, // Not reported
+            _ => self.temperature.round().clamp(0.0, u8::MAX as f64) as u8,
+        };
+        CanFrame::new(StandardId::new(self.fb_id())?, &Feedback { position, velocity: rpm, current, temperature }.encode())
+    }
+}
+
+struct SimState {
+    motors:  Vec<SimMotor>,
+    last:    Instant,
+    pending: VecDeque<CanFrame>,
+    filters: Option<Vec<CanFilter>>,
+}
+
+impl SimState {
+    // Run the physics up to `now`, queueing feedback from every motor each tick
+    fn advance(&mut self, now: Instant) {
+        let mut ticks: u32 = 0;
+        while now.duration_since(self.last) >= TICK {
+            self.last += TICK;
+            ticks += 1;
+            if ticks > MAX_CATCH_UP {
+                self.last = now;
+                break;
+            }
+            for motor in self.motors.iter_mut() {
+                for _ in 0 .. SUBSTEPS {
+                    motor.step(TICK.as_secs_f64()/SUBSTEPS as f64);
+                }
+            }
+            // Readers need every frame, not just the newest, to follow the encoder through rollovers
+            let frames: Vec<CanFrame> = self.motors.iter().filter_map(|m| m.feedback()).collect();
+            self.pending.extend(frames);
+            while self.pending.len() > MAX_PENDING {
+                self.pending.pop_front();
+            }
+        }
+    }
+}
+
+/*
+**  Virtual bus of RoboMaster motors. Responds to the command frames sent by run_once with feedback
+**  frames produced by a simple DC motor model of each motor, in real time.
+**  Used by init_bus when the interface name starts with "sim" e.g. "sim:GM6020:1,M3508:2"
+*/
+pub struct SimBus {
+    state: Mutex<SimState>,
+}
+
+impl Default for SimBus {
+    fn default() -> Self { SimBus::new() }
+}
+
+impl SimBus {
+    pub fn new() -> SimBus {
+        SimBus { state: Mutex::new(SimState { motors: Vec::new(), last: Instant::now(), pending: VecDeque::new(), filters: None }) }
+    }
+
+    /*
+    **  Parse a comma-separated list of TYPE:ID pairs e.g. "GM6020:1,M3508:2"
+    **  An empty spec gives a single GM6020 with ID 1, which is what the examples expect.
+    */
+    pub fn from_spec(spec: &str) -> Result<SimBus, RmError> {
+        let mut sim: SimBus = SimBus::new();
+        if spec.trim().is_empty() {
+            sim.add_motor(MotorType::GM6020, 1)?;
+            return Ok(sim);
+        }
+        for entry in spec.split(',') {
+            let (motor_type, id) = entry.split_once(':').ok_or_else(|| RmError::InvalidInterface(format!("Simulated motor must be given as TYPE:ID, got \"{}\"", entry)))?;
+            let motor_type: MotorType = motor_type.trim().parse().map_err(RmError::InvalidInterface)?;
+            let id: u8 = id.trim().parse().map_err(|_| RmError::InvalidInterface(format!("Invalid simulated motor ID \"{}\"", id)))?;
+            sim.add_motor(motor_type, id)?;
+        }
+        Ok(sim)
+    }
+
+    pub fn add_motor(&mut self, motor_type: MotorType, id: u8) -> Result<(), RmError> {
+        self.add_motor_with_model(motor_type, id, MotorModel::for_type(motor_type))
+    }
+
+    pub fn add_motor_with_model(&mut self, motor_type: MotorType, id: u8, model: MotorModel) -> Result<(), RmError> {
+        if id < ID_MIN || id > id_max(motor_type) {
+            return Err(RmError::IdOutOfRange { id, min: ID_MIN, max: id_max(motor_type) });
+        }
+        let motor: SimMotor = SimMotor { motor_type, id, model, drive: None, command: 0, angle: 0.0, velocity: 0.0, current: 0.0, temperature: model.ambient };
+        let state: &mut SimState = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Some(other) = state.motors.iter().find(|m| m.fb_id() == motor.fb_id()) {
+            return Err(RmError::InvalidInterface(format!("Simulated {}:{} has the same feedback ID as {}:{}", motor_type, id, other.motor_type, other.id)));
+        }
+        state.motors.push(motor);
+        Ok(())
+    }
+}
+
+impl CanTransport for SimBus {
+    fn send(&self, frame: &CanFrame) -> io::Result<()> {
+        let frame = match frame {
+            CanFrame::Data(frame) => frame,
+            _ => return Ok(()),
+        };
+        let frame_id: u16 = frame.raw_id() as u16;
+        let commands: [i16; 4] = match protocol::decode_command(frame.data()) {
+            Some(commands) => commands,
+            None => return Ok(()),
+        };
+        let mut state = lock(&self.state);
+        state.advance(Instant::now());
+        for motor in state.motors.iter_mut() {
+            if let Some((drive, slot)) = motor.cmd_slot(frame_id) {
+                motor.drive = Some(drive);
+                motor.command = commands[slot];
+            }
+        }
+        Ok(())
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<CanFrame>> {
+        let deadline: Instant = Instant::now() + timeout;
+        loop {
+            let next_tick: Instant = {
+                let mut state = lock(&self.state);
+                state.advance(Instant::now());
+                while let Some(frame) = state.pending.pop_front() {
+                    if filters_accept(state.filters.as_ref(), &frame) {
+                        return Ok(Some(frame));
+                    }
+                }
+                state.last + TICK
+            };
+            let now: Instant = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(deadline.min(next_tick).saturating_duration_since(now));
+        }
+    }
+
+    fn set_filters(&self, filters: &[CanFilter]) -> io::Result<()> {
+        lock(&self.state).filters = Some(filters.to_vec());
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use core::fmt;
+use core::ops::{BitOr, BitOrAssign};
+
+//////
+// Why a motor stopped or misbehaved, kept with the motor rather than printed: a status word of the faults seen since they were
+// last cleared, and a bounded history of when each of them happened. Both stay until clear_faults, so they still explain a stop
+// after the condition has gone away e.g. the motor cooled down or its connector was pushed back in.
+//////
+
+const HISTORY_LEN: usize = 16; // Records kept per motor, beyond which the oldest are dropped
+
+/*
+**  A condition recorded in a motor's status word and fault history, see RmMotorsCan::motor_status
+**  value is the number the fault was found with, e.g. the temperature or the requested command.
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+#[repr(C)]
+pub enum Fault {
+    #[default]
+    OverTemperature, // Commanded 0 at or above TEMP_MAX. value: temperature (C)
+    Clamped,         // A command beyond the motor's limits was clamped. value: the command (V or A)
+    StaleFeedback,   // Never responded, or lost its link. value: feedback age (ms), -1 if it never responded
+    LinkFault,       // Stopped by its link policy until clear_link_fault. value: link timeout (ms)
+    CommandTimeout,  // No command within the command timeout. value: time since the last (ms)
+    BusError,        // Sending its commands or receiving feedback failed. value: 0
+    UnsupportedMode, // Commanded in a way its mode doesn't accept e.g. move_to outside Position mode. value: the mode
+    EStop,           // Stopped by estop. value: 0
+    Derated,         // Its limit was lowered for its temperature, see TEMP_DERATE. value: temperature (C)
+}
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::OverTemperature => write!(f, "Over temperature"),
+            Fault::Clamped         => write!(f, "Command clamped"),
+            Fault::StaleFeedback   => write!(f, "Stale feedback"),
+            Fault::LinkFault       => write!(f, "Link fault"),
+            Fault::CommandTimeout  => write!(f, "Command timeout"),
+            Fault::BusError        => write!(f, "Bus error"),
+            Fault::UnsupportedMode => write!(f, "Unsupported mode"),
+            Fault::EStop           => write!(f, "Emergency stop"),
+            Fault::Derated         => write!(f, "Derated"),
+        }
+    }
+}
+const FAULTS: [Fault; 9] = [Fault::OverTemperature, Fault::Clamped, Fault::StaleFeedback, Fault::LinkFault,
+                            Fault::CommandTimeout, Fault::BusError, Fault::UnsupportedMode, Fault::EStop, Fault::Derated];
+
+/*
+**  Status word of a motor: bit (1 << fault) is set for each Fault seen since clear_faults, e.g. status.contains(Fault::Clamped)
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+#[repr(transparent)]
+pub struct MotorStatus(pub u32);
+
+impl MotorStatus {
+    pub const OK: MotorStatus = MotorStatus(0);
+
+    pub fn contains(self, fault: Fault) -> bool {
+        self.0 & MotorStatus::from(fault).0 != 0
+    }
+
+    pub fn is_ok(self) -> bool {
+        self == MotorStatus::OK
+    }
+
+    // The faults set, in the order of Fault
+    pub fn faults(self) -> impl Iterator<Item = Fault> {
+        FAULTS.into_iter().filter(move |&fault| self.contains(fault))
+    }
+}
+impl From<Fault> for MotorStatus {
+    fn from(fault: Fault) -> Self { MotorStatus(1 << fault as u32) }
+}
+impl BitOr for MotorStatus {
+    type Output = MotorStatus;
+    fn bitor(self, other: MotorStatus) -> MotorStatus { MotorStatus(self.0 | other.0) }
+}
+impl BitOrAssign for MotorStatus {
+    fn bitor_assign(&mut self, other: MotorStatus) { self.0 |= other.0 }
+}
+impl fmt::Display for MotorStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "Ok");
+        }
+        for (i, fault) in self.faults().enumerate() {
+            write!(f, "{}{}", if i == 0 { "" } else { ", " }, fault)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+**  One entry of a motor's fault history
+**  time: seconds on the clock of the bus. For MotorBus the now of the call that found it, for RmMotorsCan
+**  CLOCK_MONOTONIC (the same as get_feedback_timestamp).
+*/
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+#[repr(C)]
+pub struct FaultRecord {
+    pub fault: Fault,
+    pub time:  f64,
+    pub value: f64,
+}
+
+/*
+**  A motor's status word and history
+**  A fault is added to the history each time it starts: when it is recorded without having been in this run_once or the one before.
+**  A condition that persists e.g. a clamped setpoint is recorded once rather than every run_once, so it doesn't push everything else
+**  out, while one that comes and goes is recorded every time. The status bit stays set either way.
+*/
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct FaultLog {
+    pub status: MotorStatus,
+    active:     MotorStatus, // Recorded since the start of this run_once
+    previous:   MotorStatus, // Recorded in the run_once before
+    records:    [FaultRecord; HISTORY_LEN], // Ring buffer, next is the oldest once it is full
+    len:        usize,
+    next:       usize,
+}
+
+impl FaultLog {
+    // Set a fault, returning whether it has just started
+    pub fn record(&mut self, fault: Fault, time: f64, value: f64) -> bool {
+        self.status |= fault.into();
+        let started: bool = !(self.active | self.previous).contains(fault);
+        self.active |= fault.into();
+        if !started {
+            return false;
+        }
+        self.records[self.next] = FaultRecord { fault, time, value };
+        self.next = (self.next + 1) % HISTORY_LEN;
+        self.len = (self.len + 1).min(HISTORY_LEN);
+        true
+    }
+
+    // Start a run_once: faults not recorded again by the end of it have stopped
+    pub fn next_cycle(&mut self) {
+        self.previous = core::mem::take(&mut self.active);
+    }
+
+    // Oldest first
+    pub fn history(&self) -> impl Iterator<Item = FaultRecord> + '_ {
+        let start: usize = (self.next + HISTORY_LEN - self.len) % HISTORY_LEN;
+        (0 .. self.len).map(move |i| self.records[(start + i) % HISTORY_LEN])
+    }
+
+    pub fn clear(&mut self) {
+        *self = FaultLog::default();
+    }
+}
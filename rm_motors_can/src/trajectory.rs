@@ -0,0 +1,178 @@
+use core::time::Duration;
+
+const MAX_RAMPS: usize = 4; // Stop, speed up, cruise, slow down
+
+/*
+**  State of a motor's move_to, reported by move_status
+**  Complete means the setpoint has reached the target - check get_state for whether the motor has caught up
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+#[repr(C)]
+pub enum MoveStatus { #[default] Idle, Moving, Complete, Cancelled }
+
+/*
+**  A change of velocity from v0 to v1 with limited acceleration, and limited jerk if j_max > 0.
+**  With limited jerk the acceleration ramps up and down symmetrically, so the distance covered is (v0 + v1)/2 * duration either way.
+*/
+#[derive(Copy, Clone, Debug)]
+struct Ramp {
+    v0:       f64,
+    v1:       f64,
+    duration: f64,
+    a:        f64, // peak acceleration (magnitude)
+    t_j:      f64, // time to ramp the acceleration up to a, 0 without a jerk limit
+}
+
+impl Ramp {
+    fn new(v0: f64, v1: f64, a_max: f64, j_max: f64) -> Ramp {
+        let dv: f64 = (v1 - v0).abs();
+        let (a, t_j): (f64, f64) = if j_max <= 0.0 || dv >= a_max*a_max/j_max {
+            (a_max, if j_max > 0.0 { a_max/j_max } else { 0.0 })
+        } else {
+            // Never reaches a_max
+            (libm::sqrt(dv*j_max), libm::sqrt(dv/j_max))
+        };
+        let duration: f64 = if dv == 0.0 { 0.0 } else { dv/a + t_j };
+        Ramp { v0, v1, duration, a, t_j }
+    }
+
+    // Constant velocity for a duration
+    fn cruise(v: f64, duration: f64) -> Ramp {
+        Ramp { v0: v, v1: v, duration, a: 0.0, t_j: 0.0 }
+    }
+
+    fn distance(&self) -> f64 {
+        (self.v0 + self.v1)/2.0*self.duration
+    }
+
+    // Distance travelled and velocity at time t from the start of the ramp
+    fn sample(&self, t: f64) -> (f64, f64) {
+        let sign: f64 = if self.v1 < self.v0 { -1.0 } else { 1.0 };
+        let j: f64 = if self.t_j > 0.0 { self.a/self.t_j } else { 0.0 };
+        if t < self.t_j {
+            (self.v0*t + sign*j*t*t*t/6.0, self.v0 + sign*j*t*t/2.0)
+        }
+        else if t <= self.duration - self.t_j {
+            let tc: f64 = t - self.t_j;
+            (
+                self.v0*t + sign*(self.a*self.t_j*self.t_j/6.0 + self.a*self.t_j/2.0*tc + self.a*tc*tc/2.0),
+                self.v0 + sign*(self.a*self.t_j/2.0 + self.a*tc),
+            )
+        }
+        else {
+            // Mirror image of the start, counting back from the end
+            let r: f64 = self.duration - t;
+            (self.distance() - (self.v1*r - sign*j*r*r*r/6.0), self.v1 - sign*j*r*r/2.0)
+        }
+    }
+}
+
+/*
+**  Time-optimal move from a position and velocity to rest at a target, within velocity, acceleration and jerk limits
+**  Moving away from the target, or too fast to stop in time, it first comes to a stop and then starts over from there.
+*/
+#[derive(Clone, Debug)]
+pub(crate) struct Trajectory {
+    start:  f64,
+    target: f64,
+    ramps:  [Ramp; MAX_RAMPS], // Followed by zero-length ramps if fewer are needed
+}
+
+impl Trajectory {
+    // max_jerk <= 0 gives a trapezoidal velocity profile, otherwise an S-curve
+    pub fn new(start: f64, velocity: f64, target: f64, max_vel: f64, max_accel: f64, max_jerk: f64) -> Trajectory {
+        let ramp = |v0: f64, v1: f64| Ramp::new(v0, v1, max_accel, max_jerk);
+        let mut ramps: [Ramp; MAX_RAMPS] = [Ramp::cruise(0.0, 0.0); MAX_RAMPS];
+        let mut n: usize = 0;
+        let mut position: f64 = start;
+        let mut velocity: f64 = velocity;
+        let d: f64 = target - position;
+        if velocity*d < 0.0 || (d == 0.0 && velocity != 0.0) || ramp(velocity, 0.0).distance().abs() > d.abs() {
+            ramps[0] = ramp(velocity, 0.0);
+            n = 1;
+            position += ramps[0].distance();
+            velocity = 0.0;
+        }
+        // Work in the direction of travel
+        let d: f64 = target - position;
+        let sign: f64 = if d < 0.0 { -1.0 } else { 1.0 };
+        let (s, u): (f64, f64) = (d.abs(), velocity.abs());
+        if s > 0.0 {
+            // Distance to reach a peak velocity v and stop again
+            let covered = |v: f64| ramp(u, v).distance() + ramp(v, 0.0).distance();
+            let peak: f64 = if covered(max_vel) <= s {
+                max_vel
+            } else {
+                // Bisect between a peak that fits (u, since the stop above guarantees it) and max_vel, which doesn't
+                let (mut lo, mut hi): (f64, f64) = (u, max_vel);
+                for _ in 0 .. 60 {
+                    let mid: f64 = (lo + hi)/2.0;
+                    if covered(mid) <= s { lo = mid } else { hi = mid }
+                }
+                lo
+            };
+            let up: Ramp = ramp(u, peak);
+            let down: Ramp = ramp(peak, 0.0);
+            let cruise: f64 = if peak > 0.0 { ((s - up.distance() - down.distance())/peak).max(0.0) } else { 0.0 };
+            for r in [up, Ramp::cruise(peak, cruise), down] {
+                ramps[n] = Ramp { v0: sign*r.v0, v1: sign*r.v1, ..r };
+                n += 1;
+            }
+        }
+        Trajectory { start, target, ramps }
+    }
+
+    // Decelerate to a stop wherever that ends up
+    pub fn stop(start: f64, velocity: f64, max_accel: f64, max_jerk: f64) -> Trajectory {
+        let mut ramps: [Ramp; MAX_RAMPS] = [Ramp::cruise(0.0, 0.0); MAX_RAMPS];
+        ramps[0] = Ramp::new(velocity, 0.0, max_accel, max_jerk);
+        Trajectory { start, target: start + ramps[0].distance(), ramps }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.ramps.iter().map(|r| r.duration).sum()
+    }
+
+    // Position and velocity t seconds after the start
+    pub fn sample(&self, t: f64) -> (f64, f64) {
+        let mut position: f64 = self.start;
+        let mut t: f64 = t.max(0.0);
+        for r in &self.ramps {
+            if t < r.duration {
+                let (x, v): (f64, f64) = r.sample(t);
+                return (position + x, v);
+            }
+            position += r.distance();
+            t -= r.duration;
+        }
+        (self.target, 0.0)
+    }
+}
+
+// A trajectory being followed by one motor
+#[derive(Clone, Debug)]
+pub(crate) struct Move {
+    pub trajectory: Trajectory,
+    pub start:      Duration,
+    pub max_accel:  f64,
+    pub max_jerk:   f64,
+    pub cancelled:  bool,
+}
+
+impl Move {
+    pub fn sample(&self, now: Duration) -> (f64, f64) {
+        self.trajectory.sample(now.saturating_sub(self.start).as_secs_f64())
+    }
+
+    pub fn status(&self, now: Duration) -> MoveStatus {
+        if self.cancelled {
+            MoveStatus::Cancelled
+        }
+        else if now.saturating_sub(self.start).as_secs_f64() >= self.trajectory.duration() {
+            MoveStatus::Complete
+        }
+        else {
+            MoveStatus::Moving
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use socketcan::{CanFilter, CanFrame, CanSocket, CanTimestamps, Frame, Socket, SocketOptions};
+use socketcan::{SOF_TIMESTAMPING_OPT_CMSG, SOF_TIMESTAMPING_RAW_HARDWARE, SOF_TIMESTAMPING_RX_HARDWARE, SOF_TIMESTAMPING_RX_SOFTWARE, SOF_TIMESTAMPING_SOFTWARE};
+use std::collections::VecDeque;
+use std::io;
+use std::os::fd::AsRawFd;
+use crate::lock;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+/*
+**  When the kernel received a frame, for transports that know better than the time recv_timeout returned it
+**  e.g. SocketCAN with SO_TIMESTAMPNS and SO_TIMESTAMPING, see BusBuilder::kernel_timestamps
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct FrameTimestamps {
+    pub kernel:   Option<SystemTime>, // Wall clock time the frame entered the network stack
+    pub hardware: Option<Duration>,   // The CAN adapter's own clock, not comparable to any other
+}
+
+impl From<CanTimestamps> for FrameTimestamps {
+    fn from(ts: CanTimestamps) -> Self {
+        // The network stack's stamp is taken earlier than the socket's
+        FrameTimestamps { kernel: ts.sw.or(ts.socket), hardware: ts.hw }
+    }
+}
+
+/*
+**  A CAN bus that RmMotorsCan can send commands on and receive feedback from.
+**  The default is a Linux SocketCAN interface, but anything that can move CAN frames
+**  (an in-memory loopback, a simulator, a USB adapter with its own driver) can implement this.
+*/
+pub trait CanTransport: Send + Sync {
+    // Write a single frame to the bus
+    fn send(&self, frame: &CanFrame) -> io::Result<()>;
+    // Wait up to `timeout` for a frame to arrive. Returns Ok(None) if nothing arrived in time.
+    fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<CanFrame>>;
+    // Only receive frames matching at least one of the filters (SocketCAN semantics)
+    fn set_filters(&self, filters: &[CanFilter]) -> io::Result<()>;
+    // Same as recv_timeout, along with the frame's timestamps if the transport has any
+    fn recv_timestamped(&self, timeout: Duration) -> io::Result<Option<(CanFrame, FrameTimestamps)>> {
+        Ok(self.recv_timeout(timeout)?.map(|frame| (frame, FrameTimestamps::default())))
+    }
+}
+
+/*
+**  Have the kernel timestamp the frames a socket receives, on its wall clock, and on the adapter's clock if hardware is set
+**  Frames without a timestamp (the adapter doesn't support it, or the option isn't set) are still received.
+*/
+pub(crate) fn enable_timestamps(socket: &impl SocketOptions, hardware: bool) -> io::Result<()> {
+    socket.set_recv_timestamp(true)?;
+    let hardware: u32 = if hardware { SOF_TIMESTAMPING_RX_HARDWARE | SOF_TIMESTAMPING_RAW_HARDWARE } else { 0 };
+    socket.set_timestamping(SOF_TIMESTAMPING_RX_SOFTWARE | SOF_TIMESTAMPING_SOFTWARE | SOF_TIMESTAMPING_OPT_CMSG | hardware)
+}
+
+impl CanTransport for CanSocket {
+    fn send(&self, frame: &CanFrame) -> io::Result<()> {
+        self.write_frame(frame)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<CanFrame>> {
+        match self.read_frame_timeout(timeout) {
+            Ok(frame) => Ok(Some(frame)),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut || err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn set_filters(&self, filters: &[CanFilter]) -> io::Result<()> {
+        SocketOptions::set_filters(self, filters)
+    }
+
+    fn recv_timestamped(&self, timeout: Duration) -> io::Result<Option<(CanFrame, FrameTimestamps)>> {
+        // read_frame_with_timestamps blocks, so wait for a frame the same way read_frame_timeout does
+        let mut fd: libc::pollfd = libc::pollfd { fd: self.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let timeout_ms: i32 = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+        match unsafe { libc::poll(&mut fd, 1, timeout_ms) } {
+            0 => return Ok(None),
+            n if n < 0 => {
+                let err: io::Error = io::Error::last_os_error();
+                return if err.kind() == io::ErrorKind::Interrupted { Ok(None) } else { Err(err) };
+            },
+            _ => (),
+        }
+        let (frame, ts): (CanFrame, CanTimestamps) = self.read_frame_with_timestamps()?;
+        Ok(Some((frame, ts.into())))
+    }
+}
+
+
+#[derive(Default)]
+struct Queue {
+    frames: Mutex<VecDeque<CanFrame>>,
+    ready:  Condvar,
+}
+
+/*
+**  In-memory CAN bus with two ends. Frames sent on one end are received on the other,
+**  so one end can be handed to init_bus_with_transport and the other used to play the part of the motors.
+*/
+pub struct LoopbackTransport {
+    tx:      Arc<Queue>,
+    rx:      Arc<Queue>,
+    filters: Mutex<Option<Vec<CanFilter>>>, // None accepts everything
+}
+
+impl LoopbackTransport {
+    pub fn pair() -> (LoopbackTransport, LoopbackTransport) {
+        let a: Arc<Queue> = Arc::new(Queue::default());
+        let b: Arc<Queue> = Arc::new(Queue::default());
+        (
+            LoopbackTransport { tx: a.clone(), rx: b.clone(), filters: Mutex::new(None) },
+            LoopbackTransport { tx: b,         rx: a,         filters: Mutex::new(None) },
+        )
+    }
+
+}
+
+// Check a frame against a set of filters the same way SocketCAN does. None accepts everything.
+pub(crate) fn filters_accept(filters: Option<&Vec<CanFilter>>, frame: &CanFrame) -> bool {
+    match filters {
+        None => true,
+        Some(filters) => filters.iter().any(|f| {
+            let f: &libc::can_filter = f.as_ref();
+            (frame.raw_id() & f.can_mask) == (f.can_id & f.can_mask)
+        }),
+    }
+}
+
+impl CanTransport for LoopbackTransport {
+    fn send(&self, frame: &CanFrame) -> io::Result<()> {
+        lock(&self.tx.frames).push_back(*frame);
+        self.tx.ready.notify_all();
+        Ok(())
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<CanFrame>> {
+        let deadline: Instant = Instant::now() + timeout;
+        let mut frames = lock(&self.rx.frames);
+        loop {
+            while let Some(frame) = frames.pop_front() {
+                if filters_accept(lock(&self.filters).as_ref(), &frame) {
+                    return Ok(Some(frame));
+                }
+            }
+            let now: Instant = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            frames = self.rx.ready.wait_timeout(frames, deadline - now).unwrap_or_else(PoisonError::into_inner).0;
+        }
+    }
+
+    fn set_filters(&self, filters: &[CanFilter]) -> io::Result<()> {
+        *lock(&self.filters) = Some(filters.to_vec());
+        Ok(())
+    }
+}
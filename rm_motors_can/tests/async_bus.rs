@@ -0,0 +1,104 @@
+use embedded_can::{Frame, StandardId};
+use rm_motors_can::protocol::{self, Feedback};
+use rm_motors_can::{AsyncRmMotorsCan, CanTransport, CmdMode, FeedbackUpdate, LoopbackTransport, MotorType};
+use socketcan::CanFrame;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio::time;
+use tokio_stream::StreamExt;
+
+//////
+// AsyncRmMotorsCan over a LoopbackTransport, with the other end playing the part of a GM6020 with ID 1
+//////
+/*
+cargo test --test async_bus --features tokio
+*/
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+async fn open() -> (AsyncRmMotorsCan, Arc<LoopbackTransport>) {
+    let (bus_end, motor_end): (LoopbackTransport, LoopbackTransport) = LoopbackTransport::pair();
+    let rm_motors_can: AsyncRmMotorsCan = AsyncRmMotorsCan::with_transport(Box::new(bus_end)).await.unwrap();
+    rm_motors_can.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    (rm_motors_can, Arc::new(motor_end))
+}
+
+fn feedback_frame(feedback: Feedback) -> CanFrame {
+    CanFrame::new(StandardId::new(protocol::feedback_id(MotorType::GM6020, 1)).unwrap(), &feedback.encode()).unwrap()
+}
+
+// Send feedback at 1kHz like the motor would, until stop is set
+fn send_feedback(motor_end: Arc<LoopbackTransport>, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+    thread::spawn(move || while !stop.load(Ordering::Relaxed) {
+        motor_end.send(&feedback_frame(Feedback { temperature: 30, ..Default::default() })).unwrap();
+        thread::sleep(Duration::from_millis(1));
+    })
+}
+
+// The motor's commands in the voltage frames sent since the last call
+fn sent_commands(motor_end: &LoopbackTransport) -> Vec<i16> {
+    let mut commands: Vec<i16> = Vec::new();
+    while let Some(frame) = motor_end.recv_timeout(Duration::ZERO).unwrap() {
+        if protocol::data_frame_id(&frame) == Some(protocol::CMD_ID_V_L_6020) {
+            commands.push(protocol::decode_command(frame.data()).unwrap()[0]);
+        }
+    }
+    commands
+}
+
+#[tokio::test]
+async fn feedback_stream_yields_updates() {
+    let (rm_motors_can, motor_end): (AsyncRmMotorsCan, Arc<LoopbackTransport>) = open().await;
+    let mut feedback = Box::pin(rm_motors_can.feedback());
+    motor_end.send(&feedback_frame(Feedback { position: 0, velocity: 60, current: 0, temperature: 42 })).unwrap();
+    let update: FeedbackUpdate = time::timeout(TIMEOUT, feedback.next()).await.expect("no update").unwrap();
+    assert_eq!((update.id, update.motor_type, update.temperature), (1, MotorType::GM6020, 42.0));
+    assert!((update.velocity - 2.0*PI).abs() < 1e-9);
+    motor_end.send(&feedback_frame(Feedback { position: 0, velocity: -60, current: 0, temperature: 43 })).unwrap();
+    let update: FeedbackUpdate = time::timeout(TIMEOUT, feedback.next()).await.expect("no update").unwrap();
+    assert_eq!(update.temperature, 43.0);
+}
+
+#[tokio::test]
+async fn cleanup_ramps_the_command_to_zero() {
+    let (rm_motors_can, motor_end): (AsyncRmMotorsCan, Arc<LoopbackTransport>) = open().await;
+    let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let feedback: JoinHandle<()> = send_feedback(motor_end.clone(), stop.clone());
+    time::sleep(Duration::from_millis(10)).await;
+    rm_motors_can.set_cmd(1, 2.0).unwrap();
+    rm_motors_can.run_once().await.unwrap();
+    assert_eq!(sent_commands(&motor_end), [2083]);
+    rm_motors_can.cleanup(1).await.unwrap();
+    let ramp: Vec<i16> = sent_commands(&motor_end);
+    assert!(ramp.len() >= 10, "{:?}", ramp);
+    assert!(ramp.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", ramp);
+    assert_eq!(ramp.last(), Some(&0));
+    stop.store(true, Ordering::Relaxed);
+    feedback.join().unwrap();
+}
+
+#[tokio::test]
+async fn start_loop_replaces_the_running_loop() {
+    let (rm_motors_can, motor_end): (AsyncRmMotorsCan, Arc<LoopbackTransport>) = open().await;
+    let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let feedback: JoinHandle<()> = send_feedback(motor_end.clone(), stop.clone());
+    time::sleep(Duration::from_millis(10)).await;
+    rm_motors_can.start_loop(1_000).unwrap();
+    time::sleep(Duration::from_millis(20)).await;
+    assert!(!sent_commands(&motor_end).is_empty());
+    // At 20ms alone this sends about 5 frames in 100ms, along with the 1ms loop about 100
+    rm_motors_can.start_loop(20_000).unwrap();
+    time::sleep(Duration::from_millis(100)).await;
+    let sent: usize = sent_commands(&motor_end).len();
+    assert!((2..=10).contains(&sent), "{} frames in 100ms", sent);
+    rm_motors_can.stop_loop();
+    time::sleep(Duration::from_millis(30)).await;
+    sent_commands(&motor_end);
+    time::sleep(Duration::from_millis(30)).await;
+    assert!(sent_commands(&motor_end).is_empty());
+    stop.store(true, Ordering::Relaxed);
+    feedback.join().unwrap();
+}
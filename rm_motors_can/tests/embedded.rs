@@ -0,0 +1,546 @@
+use embedded_can::{blocking::Can, ErrorKind, ExtendedId, Frame, Id, StandardId};
+use rm_motors_can::protocol::{self, Feedback};
+use rm_motors_can::{CmdMode, Fault, FaultRecord, FbField, LinkPolicy, LinkStatus, MotorBus, MotorId, MotorType, MoveStatus, RmError};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+//////
+// MotorBus, the no_std core, over a mock embedded_can driver. Runs with or without the std feature:
+//////
+/*
+cargo test --test embedded --no-default-features
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+struct MockFrame {
+    id:     Id,
+    remote: bool,
+    data:   Vec<u8>,
+}
+
+impl Frame for MockFrame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        (data.len() <= 8).then(|| MockFrame { id: id.into(), remote: false, data: data.to_vec() })
+    }
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        (dlc <= 8).then(|| MockFrame { id: id.into(), remote: true, data: vec![0; dlc] })
+    }
+    fn is_extended(&self) -> bool { matches!(self.id, Id::Extended(_)) }
+    fn is_remote_frame(&self) -> bool { self.remote }
+    fn id(&self) -> Id { self.id }
+    fn dlc(&self) -> usize { self.data.len() }
+    fn data(&self) -> &[u8] { &self.data }
+}
+
+// Frames queued for receive, frames recorded on transmit, and an error to fail the next transmit with
+#[derive(Default)]
+struct MockCan {
+    rx:       VecDeque<MockFrame>,
+    tx:       Vec<MockFrame>,
+    tx_error: Option<ErrorKind>,
+}
+
+impl Can for MockCan {
+    type Frame = MockFrame;
+    type Error = ErrorKind;
+
+    fn transmit(&mut self, frame: &MockFrame) -> Result<(), ErrorKind> {
+        match self.tx_error.take() {
+            Some(err) => Err(err),
+            None => {self.tx.push(frame.clone()); Ok(())},
+        }
+    }
+
+    fn receive(&mut self) -> Result<MockFrame, ErrorKind> {
+        self.rx.pop_front().ok_or(ErrorKind::Overrun)
+    }
+}
+
+fn feedback_frame(motor_type: MotorType, id: u8, feedback: Feedback) -> MockFrame {
+    MockFrame::new(StandardId::new(protocol::feedback_id(motor_type, id)).unwrap(), &feedback.encode()).unwrap()
+}
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+// Commands sent in the frame with the given ID
+fn sent(bus: &mut MotorBus<MockCan>, frame_id: u16) -> Option<[i16; 4]> {
+    bus.can().tx.iter().rev()
+        .find(|frame| frame.id == Id::Standard(StandardId::new(frame_id).unwrap()))
+        .and_then(|frame| protocol::decode_command(&frame.data))
+}
+
+#[test]
+fn voltage_command_is_sent_in_the_motor_slot() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(2, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 2, Feedback::default()), ms(0));
+    bus.set_cmd(2, 12.0).unwrap();
+    bus.run_once(ms(1)).unwrap();
+    assert_eq!(bus.can().tx.len(), 1);
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020), Some([0, 12500, 0, 0]));
+}
+
+#[test]
+fn motors_sharing_a_frame_are_sent_together() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::M3508, CmdMode::Current).unwrap();
+    bus.init_motor(4, MotorType::M2006, CmdMode::Current).unwrap();
+    for (motor_type, id) in [(MotorType::M3508, 1), (MotorType::M2006, 4)] {
+        bus.can().rx.push_back(feedback_frame(motor_type, id, Feedback::default()));
+        assert_eq!(bus.receive(ms(0)).unwrap(), Some(MotorId::Typed(motor_type, id)));
+    }
+    bus.set_cmd(1, 10.0).unwrap();
+    bus.set_cmd(4, -5.0).unwrap();
+    bus.run_once(ms(1)).unwrap();
+    assert_eq!(bus.can().tx.len(), 1);
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_I_L_3508), Some([8192, 0, 0, -5000]));
+}
+
+#[test]
+fn feedback_updates_the_state() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    let feedback: Feedback = Feedback { position: 4096, velocity: 60, current: -8192, temperature: 40 };
+    assert_eq!(bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, feedback), ms(5)), Some(MotorId::Typed(MotorType::GM6020, 1)));
+    assert!((bus.get_state(1, FbField::Position).unwrap() - 4096.0/8191.0*2.0*PI).abs() < 1e-9);
+    assert!((bus.get_state(1, FbField::Velocity).unwrap() - 2.0*PI).abs() < 1e-9);
+    assert!((bus.get_state(1, FbField::Current).unwrap() + 0.81).abs() < 1e-9);
+    assert_eq!(bus.get_state(1, FbField::Temperature).unwrap(), 40.0);
+    assert_eq!(bus.feedback_age(1, ms(12)).unwrap(), ms(7));
+}
+
+#[test]
+fn encoder_rollovers_are_counted() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    for (t, position) in [8000u16, 100, 4000, 8100, 50].into_iter().enumerate() {
+        bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback { position, ..Default::default() }), ms(t as u64));
+    }
+    // Forward over 0 twice, backward once
+    assert!((bus.get_state(1, FbField::MultiTurn).unwrap() - (8192.0 + 50.0)/8192.0*2.0*PI).abs() < 1e-9);
+    bus.zero_position(1).unwrap();
+    assert_eq!(bus.get_state(1, FbField::MultiTurn).unwrap(), 0.0);
+}
+
+#[test]
+fn upper_3508_ids_resolve_once_initialized() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    let frame: MockFrame = feedback_frame(MotorType::M3508, 6, Feedback { position: 1000, ..Default::default() });
+    // 0x206 is GM6020 2 until an M3508 with ID 6 is initialized
+    assert_eq!(bus.handle_frame(&frame, ms(0)), Some(MotorId::Typed(MotorType::GM6020, 2)));
+    bus.init_motor(6, MotorType::M3508, CmdMode::Current).unwrap();
+    assert_eq!(bus.handle_frame(&frame, ms(1)), Some(MotorId::Typed(MotorType::M3508, 6)));
+    assert!(matches!(bus.init_motor(2, MotorType::GM6020, CmdMode::Voltage),
+        Err(RmError::IdCollision { motor_type: MotorType::GM6020, id: 2, other: MotorType::M3508, other_id: 6 })));
+    // Only the IDs that share a CAN ID collide
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    assert!(matches!(bus.init_motor(5, MotorType::M2006, CmdMode::Current),
+        Err(RmError::IdCollision { motor_type: MotorType::M2006, id: 5, other: MotorType::GM6020, other_id: 1 })));
+}
+
+#[test]
+fn eleven_motors_share_a_bus() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    let motors: Vec<(MotorType, u8)> = (1 ..= 8).map(|id| (MotorType::M3508, id)).chain((5 ..= 7).map(|id| (MotorType::GM6020, id))).collect();
+    for &(motor_type, id) in &motors {
+        let mode: CmdMode = if motor_type == MotorType::GM6020 { CmdMode::Voltage } else { CmdMode::Current };
+        bus.init_motor(id, motor_type, mode).unwrap();
+        let feedback: Feedback = Feedback { temperature: 20 + id + if motor_type == MotorType::GM6020 { 10 } else { 0 }, ..Default::default() };
+        assert_eq!(bus.handle_frame(&feedback_frame(motor_type, id, feedback), ms(0)), Some(MotorId::Typed(motor_type, id)));
+    }
+    // Each motor keeps its own feedback, and IDs used by both families need the type
+    assert_eq!(bus.get_state((MotorType::M3508, 5), FbField::Temperature).unwrap(), 25.0);
+    assert_eq!(bus.get_state((MotorType::GM6020, 5), FbField::Temperature).unwrap(), 35.0);
+    assert_eq!(bus.get_state(8, FbField::Temperature).unwrap(), 28.0);
+    assert!(matches!(bus.get_state(5, FbField::Temperature), Err(RmError::AmbiguousId { id: 5 })));
+
+    for &(motor_type, id) in &motors {
+        bus.set_cmd((motor_type, id), 1.0).unwrap();
+    }
+    bus.run_once(ms(1)).unwrap();
+    assert_eq!(bus.can().tx.len(), 3);
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_I_L_3508), Some([819; 4]));
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_I_H_3508), Some([819; 4]));
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_H_6020), Some([1041, 1041, 1041, 0]));
+}
+
+#[test]
+fn other_frames_are_ignored() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    let data: [u8; 8] = Feedback { position: 1000, ..Default::default() }.encode();
+    let frames: [MockFrame; 4] = [
+        MockFrame::new(StandardId::new(protocol::CMD_ID_V_L_6020).unwrap(), &data).unwrap(),
+        MockFrame::new(ExtendedId::new(0x205).unwrap(), &data).unwrap(),
+        MockFrame::new_remote(StandardId::new(0x205).unwrap(), 8).unwrap(),
+        MockFrame::new(StandardId::new(0x205).unwrap(), &data[.. 6]).unwrap(),
+    ];
+    for frame in &frames {
+        assert_eq!(bus.handle_frame(frame, ms(0)), None);
+    }
+    assert!(matches!(bus.feedback_age(1, ms(0)), Err(RmError::StaleFeedback { id: 1, age: None })));
+}
+
+#[test]
+fn run_once_needs_feedback_from_every_motor() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    assert!(matches!(bus.run_once(ms(0)), Err(RmError::StaleFeedback { id: 1, age: None })));
+    assert!(bus.can().tx.is_empty());
+}
+
+#[test]
+fn can_errors_are_reported() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    assert!(matches!(bus.receive(ms(0)), Err(RmError::Can(ErrorKind::Overrun))));
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(0));
+    bus.can().tx_error = Some(ErrorKind::Bit);
+    assert!(matches!(bus.run_once(ms(1)), Err(RmError::Can(ErrorKind::Bit))));
+    bus.run_once(ms(2)).unwrap();
+}
+
+#[test]
+fn velocity_controller_drives_towards_the_setpoint() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Velocity).unwrap();
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(0));
+    bus.set_cmd(1, 5.0).unwrap();
+    bus.run_once(ms(1)).unwrap();
+    let forward: i16 = sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0];
+    assert!(forward > 0);
+    // Overshooting turns the command around
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback { velocity: 200, ..Default::default() }), ms(2));
+    bus.set_velocity_gains(1, 2.0, 0.0, 0.0).unwrap();
+    bus.run_once(ms(3)).unwrap();
+    assert!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0] < 0);
+    assert_eq!(bus.get_stage_signal(1, rm_motors_can::Stage::Velocity, rm_motors_can::StageSignal::Setpoint).unwrap(), 5.0);
+}
+
+#[test]
+fn move_to_follows_the_supplied_clock() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Position).unwrap();
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(0));
+    // 1 rad at up to 1 rad/s and 10 rad/s^2 takes 1.1s
+    bus.move_to(1, 1.0, 1.0, 10.0, ms(0)).unwrap();
+    assert_eq!(bus.move_status(1, ms(1000)).unwrap(), MoveStatus::Moving);
+    assert_eq!(bus.move_status(1, ms(1100)).unwrap(), MoveStatus::Complete);
+    bus.run_once(ms(500)).unwrap();
+    assert!((bus.get_stage_signal(1, rm_motors_can::Stage::Position, rm_motors_can::StageSignal::Setpoint).unwrap() - 0.45).abs() < 1e-9);
+    bus.cancel_move(1, ms(600)).unwrap();
+    assert_eq!(bus.move_status(1, ms(600)).unwrap(), MoveStatus::Cancelled);
+    assert!(bus.move_to(2, 1.0, 1.0, 10.0, ms(0)).is_err());
+}
+
+#[test]
+fn position_mode_holds_the_first_feedback_position() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Position).unwrap();
+    // Half a turn from the encoder's zero
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback { position: 4096, ..Default::default() }), ms(0));
+    bus.run_once(ms(1)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 0);
+    let position: f64 = bus.get_state(1, FbField::MultiTurn).unwrap();
+    assert_eq!(bus.get_stage_signal(1, rm_motors_can::Stage::Position, rm_motors_can::StageSignal::Setpoint).unwrap(), position);
+}
+
+#[test]
+fn a_setpoint_given_before_feedback_is_kept() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Position).unwrap();
+    bus.set_cmd(1, 1.0).unwrap();
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback { position: 4096, ..Default::default() }), ms(0));
+    bus.run_once(ms(1)).unwrap();
+    assert_eq!(bus.get_stage_signal(1, rm_motors_can::Stage::Position, rm_motors_can::StageSignal::Setpoint).unwrap(), 1.0);
+}
+
+#[test]
+fn non_finite_commands_are_rejected() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Position).unwrap();
+    bus.init_motor(2, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    for cmd in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        assert!(matches!(bus.set_cmd(1, cmd), Err(RmError::InvalidParameter { name: "cmd", .. })));
+        assert!(matches!(bus.set_cmd(2, cmd), Err(RmError::InvalidParameter { name: "cmd", .. })));
+    }
+}
+
+#[test]
+fn a_fault_that_comes_back_is_recorded_again() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    bus.set_command_timeout(1, 2, 0).unwrap();
+    // Trip the watchdog 20 times, each at t = 10*trip + 5ms
+    for trip in 0 .. 20 {
+        let t: u64 = 10*trip;
+        bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(t));
+        bus.set_cmd(1, 1.0).unwrap();
+        bus.run_once(ms(t)).unwrap();
+        bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(t + 5));
+        assert!(matches!(bus.run_once(ms(t + 5)), Err(RmError::CommandTimeout { id: 1, .. })));
+        // Still timed out, which isn't recorded again
+        assert!(bus.run_once(ms(t + 6)).is_err());
+    }
+    assert!(bus.motor_status(1).unwrap().contains(Fault::CommandTimeout));
+    let history: Vec<FaultRecord> = bus.fault_history(1).unwrap().collect();
+    assert_eq!(history.len(), 16);
+    assert!(history.iter().all(|record| record.fault == Fault::CommandTimeout));
+    // The first 4 are dropped
+    assert_eq!(history[0].time, 0.045);
+    assert_eq!(history[15].time, 0.195);
+    bus.clear_faults(1).unwrap();
+    assert_eq!(bus.fault_history(1).unwrap().count(), 0);
+}
+
+#[test]
+fn command_timeout_ramps_to_zero() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    bus.set_command_timeout(1, 100, 50).unwrap();
+    let step = |bus: &mut MotorBus<MockCan>, t: u64| -> (Result<(), RmError>, i16) {
+        bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(t));
+        let r: Result<(), RmError> = bus.run_once(ms(t));
+        (r, sent(bus, protocol::CMD_ID_V_L_6020).unwrap()[0])
+    };
+    bus.set_cmd(1, 12.0).unwrap();
+    assert!(matches!(step(&mut bus, 0), (Ok(()), 12500)));
+    assert!(matches!(step(&mut bus, 99), (Ok(()), 12500)));
+    // Trips at 100ms, then ramps down over 50ms
+    assert!(matches!(step(&mut bus, 100), (Err(RmError::CommandTimeout { id: 1, .. }), 12500)));
+    assert!(matches!(step(&mut bus, 125), (Err(RmError::CommandTimeout { .. }), 6250)));
+    assert!(matches!(step(&mut bus, 150), (Err(RmError::CommandTimeout { .. }), 0)));
+    assert!(matches!(step(&mut bus, 500), (Err(RmError::CommandTimeout { .. }), 0)));
+    assert!(bus.motor_status(1).unwrap().contains(Fault::CommandTimeout));
+}
+
+#[test]
+fn set_cmd_rearms_the_command_timeout() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(0));
+    bus.set_command_timeout(1, 100, 0).unwrap();
+    bus.set_cmd(1, 12.0).unwrap();
+    bus.run_once(ms(0)).unwrap();
+    assert!(bus.run_once(ms(100)).is_err());
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 0);
+    // The new command is sent, and the timeout counts from the run_once that first sees it
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(150));
+    bus.set_cmd(1, 6.0).unwrap();
+    bus.run_once(ms(150)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 6250);
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(249));
+    bus.run_once(ms(249)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 6250);
+    assert!(bus.run_once(ms(250)).is_err());
+    // Turning it off lets the command stand
+    bus.set_command_timeout(1, 0, 0).unwrap();
+    bus.set_cmd(1, 6.0).unwrap();
+    bus.run_once(ms(1000)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 6250);
+}
+
+// Two GM6020s in Voltage mode, sharing a command frame, with the given link policy on ID 1 and a 50ms timeout
+fn link_bus(policy: LinkPolicy) -> MotorBus<MockCan> {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    for id in [1, 2] {
+        bus.init_motor(id, MotorType::GM6020, CmdMode::Voltage).unwrap();
+        bus.handle_frame(&feedback_frame(MotorType::GM6020, id, Feedback::default()), ms(0));
+        bus.set_cmd(id, 12.0).unwrap();
+    }
+    bus.set_link_policy(1, policy, 50).unwrap();
+    bus.run_once(ms(0)).unwrap();
+    bus
+}
+
+// Feedback from the given motors, then run_once, returning its result and the commands sent
+fn link_step(bus: &mut MotorBus<MockCan>, ids: &[u8], t: u64) -> (Result<(), RmError>, [i16; 2]) {
+    for &id in ids {
+        bus.handle_frame(&feedback_frame(MotorType::GM6020, id, Feedback::default()), ms(t));
+    }
+    let r: Result<(), RmError> = bus.run_once(ms(t));
+    let commands: [i16; 4] = sent(bus, protocol::CMD_ID_V_L_6020).unwrap();
+    (r, [commands[0], commands[1]])
+}
+
+#[test]
+fn warn_keeps_commanding_a_lost_motor() {
+    let mut bus: MotorBus<MockCan> = link_bus(LinkPolicy::Warn);
+    assert!(matches!(link_step(&mut bus, &[2], 49), (Ok(()), [12500, 12500])));
+    assert_eq!(bus.link_status(1, ms(49)).unwrap(), LinkStatus::Stale);
+    assert!(matches!(link_step(&mut bus, &[2], 60), (Ok(()), [12500, 12500])));
+    assert_eq!(bus.link_status(1, ms(60)).unwrap(), LinkStatus::Lost);
+    assert!(bus.motor_status(1).unwrap().contains(Fault::StaleFeedback));
+    assert!(matches!(link_step(&mut bus, &[1, 2], 70), (Ok(()), [12500, 12500])));
+    assert_eq!(bus.link_status(1, ms(70)).unwrap(), LinkStatus::Ok);
+}
+
+#[test]
+fn zero_command_stops_a_lost_motor_until_it_is_commanded_again() {
+    let mut bus: MotorBus<MockCan> = link_bus(LinkPolicy::ZeroCommand);
+    assert!(matches!(link_step(&mut bus, &[2], 60), (Err(RmError::StaleFeedback { id: 1, age: Some(_) }), [0, 12500])));
+    // Commands while it is lost aren't sent
+    bus.set_cmd(1, 6.0).unwrap();
+    assert!(matches!(link_step(&mut bus, &[2], 61), (Err(RmError::StaleFeedback { id: 1, .. }), [0, 12500])));
+    // Back, but stopped until the next command
+    assert!(matches!(link_step(&mut bus, &[1, 2], 70), (Ok(()), [0, 12500])));
+    bus.set_cmd(1, 6.0).unwrap();
+    assert!(matches!(link_step(&mut bus, &[1, 2], 71), (Ok(()), [6250, 12500])));
+    assert!(!bus.motor_status(1).unwrap().contains(Fault::LinkFault));
+}
+
+#[test]
+fn disable_stops_a_lost_motor_until_clear_link_fault() {
+    let mut bus: MotorBus<MockCan> = link_bus(LinkPolicy::Disable);
+    assert!(matches!(link_step(&mut bus, &[2], 60), (Err(RmError::StaleFeedback { id: 1, .. }), [0, 12500])));
+    assert!(matches!(bus.clear_link_fault(1, ms(60)), Err(RmError::StaleFeedback { id: 1, .. })));
+    // Stays stopped once it is back
+    bus.set_cmd(1, 6.0).unwrap();
+    assert!(matches!(link_step(&mut bus, &[1, 2], 70), (Err(RmError::LinkFault { id: 1 }), [0, 12500])));
+    assert!(bus.motor_status(1).unwrap().contains(Fault::LinkFault));
+    bus.clear_link_fault(1, ms(70)).unwrap();
+    bus.set_cmd(1, 6.0).unwrap();
+    assert!(matches!(link_step(&mut bus, &[1, 2], 71), (Ok(()), [6250, 12500])));
+}
+
+#[test]
+fn fault_bus_stops_every_motor_until_clear_link_fault() {
+    let mut bus: MotorBus<MockCan> = link_bus(LinkPolicy::FaultBus);
+    assert!(matches!(link_step(&mut bus, &[2], 60), (Err(RmError::StaleFeedback { id: 1, .. }), [0, 0])));
+    bus.set_cmd(2, 6.0).unwrap();
+    assert!(matches!(link_step(&mut bus, &[1, 2], 70), (Err(RmError::LinkFault { id: 1 }), [0, 0])));
+    assert!(!bus.motor_status(2).unwrap().contains(Fault::LinkFault));
+    bus.clear_link_fault(1, ms(70)).unwrap();
+    for id in [1, 2] {
+        bus.set_cmd(id, 6.0).unwrap();
+    }
+    assert!(matches!(link_step(&mut bus, &[1, 2], 71), (Ok(()), [6250, 6250])));
+}
+
+#[test]
+fn estop_zeros_every_command_frame_until_clear_fault() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    bus.init_motor(2, MotorType::GM6020, CmdMode::Position).unwrap();
+    for id in [1, 2] {
+        bus.handle_frame(&feedback_frame(MotorType::GM6020, id, Feedback::default()), ms(0));
+    }
+    bus.set_cmd(1, 12.0).unwrap();
+    bus.run_once(ms(0)).unwrap();
+    let before: usize = bus.can().tx.len();
+    // Sent at once, in every command frame whether or not a motor uses it
+    bus.estop().unwrap();
+    assert!(bus.faulted());
+    let estop: Vec<MockFrame> = bus.can().tx.split_off(before);
+    let ids: Vec<Id> = protocol::CMD_IDS.iter().map(|&frame_id| Id::Standard(StandardId::new(frame_id).unwrap())).collect();
+    assert_eq!(estop.iter().map(|frame| frame.id).collect::<Vec<Id>>(), ids);
+    assert!(estop.iter().all(|frame| protocol::decode_command(&frame.data) == Some([0; 4])));
+    assert!(matches!(bus.set_cmd(1, 12.0), Err(RmError::EStopped)));
+    assert!(matches!(bus.move_to(2, 1.0, 1.0, 10.0, ms(1)), Err(RmError::EStopped)));
+    // run_once keeps sending zeros, whatever the feedback
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 2, Feedback { position: 4096, ..Default::default() }), ms(1));
+    bus.run_once(ms(1)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020), Some([0; 4]));
+    assert!(bus.motor_status(1).unwrap().contains(Fault::EStop));
+    // Stopped until commanded again
+    bus.clear_fault();
+    assert!(!bus.faulted());
+    bus.run_once(ms(2)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020), Some([0; 4]));
+    bus.set_cmd(1, 6.0).unwrap();
+    bus.move_to(2, 1.0, 1.0, 10.0, ms(3)).unwrap();
+    bus.run_once(ms(3)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 6250);
+}
+
+fn temperature(temperature: u8) -> Feedback {
+    Feedback { temperature, ..Default::default() }
+}
+
+#[test]
+fn hot_motors_are_derated_then_cut_off() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, temperature(100)), ms(0));
+    bus.set_cmd(1, 24.0).unwrap();
+    bus.run_once(ms(0)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 25000);
+    assert!(bus.motor_status(1).unwrap().is_ok());
+    // 60% of the limit 10C above TEMP_DERATE, without setting the command again
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, temperature(110)), ms(1));
+    bus.run_once(ms(1)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 15000);
+    assert!(bus.motor_status(1).unwrap().contains(Fault::Derated));
+    // Cut off at TEMP_MAX
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, temperature(125)), ms(2));
+    assert!(matches!(bus.run_once(ms(2)), Err(RmError::OverTemperature { id: 1, temperature: 125, limit: 125 })));
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 0);
+    assert!(matches!(bus.set_cmd(1, 24.0), Err(RmError::OverTemperature { .. })));
+    assert!(bus.motor_status(1).unwrap().contains(Fault::OverTemperature));
+    // Only eases off once it has cooled by 5C
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, temperature(121)), ms(3));
+    assert!(bus.run_once(ms(3)).is_err());
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, temperature(115)), ms(4));
+    // The cut-off zeroed the command, so it stays stopped until commanded again
+    bus.run_once(ms(4)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 0);
+    bus.set_cmd(1, 24.0).unwrap();
+    bus.run_once(ms(4)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 5000);
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, temperature(50)), ms(5));
+    bus.run_once(ms(5)).unwrap();
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 25000);
+    // The history has each of them once
+    let faults: Vec<Fault> = bus.fault_history(1).unwrap().map(|record| record.fault).collect();
+    assert_eq!(faults, [Fault::Derated, Fault::OverTemperature, Fault::Derated]);
+}
+
+#[test]
+fn derating_limits_the_controllers() {
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Velocity).unwrap();
+    bus.set_temperature_limits(1, 50, 100).unwrap();
+    bus.set_cmd(1, 10.0).unwrap();
+    // Stalled at 75C, half the limit
+    for t in 0 .. 100 {
+        bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, temperature(75)), ms(t));
+        bus.run_once(ms(t)).unwrap();
+    }
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], 12500);
+    for stage in [rm_motors_can::Stage::Velocity, rm_motors_can::Stage::Current] {
+        assert_eq!(bus.get_stage_signal(1, stage, rm_motors_can::StageSignal::Output).unwrap(), 12.0);
+    }
+    // Not wound up past it, so it comes off the limit as soon as the target is reached
+    bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback { velocity: 95, temperature: 75, ..Default::default() }), ms(100));
+    bus.run_once(ms(100)).unwrap();
+    assert!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0] < 12500);
+    assert!(matches!(bus.set_temperature_limits(1, 100, 100), Err(RmError::InvalidParameter { .. })));
+}
+
+#[test]
+fn closing_a_gm6020_current_loop_resets_the_velocity_limit() {
+    use rm_motors_can::{i_max, Stage, StageSignal, V_MAX};
+    let mut bus: MotorBus<MockCan> = MotorBus::new(MockCan::default());
+    bus.init_motor(1, MotorType::GM6020, CmdMode::Velocity).unwrap();
+    bus.set_cmd(1, 100.0).unwrap();
+    // Far beyond its speed and stalled, so the Velocity stage is held at its limit
+    let step = |bus: &mut MotorBus<MockCan>, t: u64| -> f64 {
+        bus.handle_frame(&feedback_frame(MotorType::GM6020, 1, Feedback::default()), ms(t));
+        bus.run_once(ms(t)).unwrap();
+        bus.get_stage_signal(1, Stage::Velocity, StageSignal::Output).unwrap()
+    };
+    assert_eq!(step(&mut bus, 0), V_MAX);
+    // Its output is now in A
+    bus.set_stage_gains(1, Stage::Current, 2.0, 0.0, 0.0, 0.0).unwrap();
+    assert_eq!(step(&mut bus, 1), i_max(MotorType::GM6020));
+    assert_eq!(sent(&mut bus, protocol::CMD_ID_V_L_6020).unwrap()[0], (2.0*i_max(MotorType::GM6020)/V_MAX*25000.0) as i16);
+    // A limit set while the loop is closed is kept until it passes through again
+    bus.set_stage_limit(1, Stage::Velocity, 1.0).unwrap();
+    bus.set_stage_gains(1, Stage::Current, 3.0, 0.0, 0.0, 0.0).unwrap();
+    assert_eq!(step(&mut bus, 2), 1.0);
+    bus.set_stage_gains(1, Stage::Current, 0.0, 0.0, 0.0, 1.0).unwrap();
+    assert_eq!(step(&mut bus, 3), V_MAX);
+}
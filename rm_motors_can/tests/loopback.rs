@@ -0,0 +1,288 @@
+use embedded_can::{Frame, StandardId};
+use rm_motors_can::protocol::{self, Feedback};
+use rm_motors_can::{CanTransport, V_MAX, CmdMode, FbField, FeedbackTime, FrameTimestamps, LinkStatus, LoopStat, LoopbackTransport, MotorType, RmError, RmMotorsCan, TimestampSource};
+use socketcan::{CanFilter, CanFrame};
+use std::io;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+//////
+// RmMotorsCan over a LoopbackTransport, with the other end playing the part of the motors
+//////
+
+const LISTEN: Duration = Duration::from_millis(10);
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// A bus with one GM6020 in the given mode, and the motors' end of the loopback
+fn open(mode: CmdMode) -> (Arc<RmMotorsCan>, LoopbackTransport) {
+    let (bus_end, motor_end): (LoopbackTransport, LoopbackTransport) = LoopbackTransport::pair();
+    let rm_motors_can: Arc<RmMotorsCan> = RmMotorsCan::builder().transport(Box::new(bus_end)).listen_time(LISTEN).build().unwrap();
+    rm_motors_can.init_motor(1, MotorType::GM6020, mode).unwrap();
+    (rm_motors_can, motor_end)
+}
+
+// Send feedback from GM6020 ID 1, and wait for the receive thread to have applied it
+fn send_feedback(rm_motors_can: &RmMotorsCan, motor_end: &LoopbackTransport, feedback: Feedback) {
+    let frame: CanFrame = CanFrame::new(StandardId::new(protocol::feedback_id(MotorType::GM6020, 1)).unwrap(), &feedback.encode()).unwrap();
+    motor_end.send(&frame).unwrap();
+    let t: Instant = Instant::now();
+    while rm_motors_can.get_state(1, FbField::Temperature).unwrap() != feedback.temperature as f64 {
+        assert!(t.elapsed() < TIMEOUT, "feedback wasn't received");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// The commands of the next frame the motors receive with the given ID
+fn recv_command(motor_end: &LoopbackTransport, frame_id: u16) -> [i16; 4] {
+    loop {
+        let frame: CanFrame = motor_end.recv_timeout(TIMEOUT).unwrap().expect("no command was sent");
+        if protocol::data_frame_id(&frame) == Some(frame_id) {
+            return protocol::decode_command(frame.data()).unwrap();
+        }
+    }
+}
+
+#[test]
+fn set_cmd_is_sent_by_run_once() {
+    let (rm_motors_can, motor_end): (Arc<RmMotorsCan>, LoopbackTransport) = open(CmdMode::Voltage);
+    send_feedback(&rm_motors_can, &motor_end, Feedback { temperature: 30, ..Default::default() });
+    rm_motors_can.set_cmd(1, 12.0).unwrap();
+    // Nothing is sent until run_once
+    assert!(motor_end.recv_timeout(Duration::from_millis(10)).unwrap().is_none());
+    rm_motors_can.run_once().unwrap();
+    assert_eq!(recv_command(&motor_end, protocol::CMD_ID_V_L_6020), [12500, 0, 0, 0]);
+    rm_motors_can.set_cmd(1, -6.0).unwrap();
+    rm_motors_can.run_once().unwrap();
+    assert_eq!(recv_command(&motor_end, protocol::CMD_ID_V_L_6020), [-6250, 0, 0, 0]);
+}
+
+#[test]
+fn get_state_follows_the_feedback() {
+    let (rm_motors_can, motor_end): (Arc<RmMotorsCan>, LoopbackTransport) = open(CmdMode::Current);
+    send_feedback(&rm_motors_can, &motor_end, Feedback { position: 4096, velocity: 60, current: 0, temperature: 40 });
+    assert!((rm_motors_can.get_state(1, FbField::Position).unwrap() - 4096.0/8191.0*2.0*PI).abs() < 1e-9);
+    assert!((rm_motors_can.get_state(1, FbField::Velocity).unwrap() - 2.0*PI).abs() < 1e-9);
+    assert_eq!(rm_motors_can.get_state(1, FbField::Temperature).unwrap(), 40.0);
+    send_feedback(&rm_motors_can, &motor_end, Feedback { position: 0, velocity: -60, current: 0, temperature: 41 });
+    assert!((rm_motors_can.get_state(1, FbField::Velocity).unwrap() + 2.0*PI).abs() < 1e-9);
+}
+
+#[test]
+fn run_once_needs_feedback() {
+    let (rm_motors_can, motor_end): (Arc<RmMotorsCan>, LoopbackTransport) = open(CmdMode::Voltage);
+    assert!(matches!(rm_motors_can.run_once(), Err(RmError::StaleFeedback { id: 1, age: None })));
+    assert!(motor_end.recv_timeout(Duration::from_millis(10)).unwrap().is_none());
+}
+
+#[test]
+fn another_controller_is_detected() {
+    let (bus_end, motor_end): (LoopbackTransport, LoopbackTransport) = LoopbackTransport::pair();
+    let frame: CanFrame = protocol::command_frame(protocol::CMD_ID_V_L_6020, [0; 4]).unwrap();
+    motor_end.send(&frame).unwrap();
+    let r: Result<Arc<RmMotorsCan>, RmError> = RmMotorsCan::builder().transport(Box::new(bus_end)).listen_time(LISTEN).build();
+    assert!(matches!(r, Err(RmError::ConflictingController)));
+}
+
+// Wait until the loop has run at least n iterations
+fn wait_for_iterations(rm_motors_can: &RmMotorsCan, n: f64) {
+    let t: Instant = Instant::now();
+    while rm_motors_can.get_loop_stat(LoopStat::Iterations).unwrap() < n {
+        assert!(t.elapsed() < TIMEOUT, "the loop isn't running");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn the_loop_can_be_restarted_straight_after_stop_loop() {
+    let (rm_motors_can, _motor_end): (Arc<RmMotorsCan>, LoopbackTransport) = open(CmdMode::Voltage);
+    rm_motors_can.start_loop(10_000).unwrap();
+    wait_for_iterations(&rm_motors_can, 2.0);
+    // The loop is still sleeping out its period, so it hasn't exited when start_loop is called
+    rm_motors_can.stop_loop().unwrap();
+    rm_motors_can.start_loop(10_000).unwrap();
+    // Past the iteration the stopped loop was in
+    thread::sleep(Duration::from_millis(30));
+    let before: f64 = rm_motors_can.get_loop_stat(LoopStat::Iterations).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert!(rm_motors_can.get_loop_stat(LoopStat::Iterations).unwrap() > before, "the restarted loop quit");
+    rm_motors_can.stop_loop().unwrap();
+    rm_motors_can.join_loop().unwrap();
+}
+
+#[test]
+fn loop_stats_count_the_iterations() {
+    let (rm_motors_can, _motor_end): (Arc<RmMotorsCan>, LoopbackTransport) = open(CmdMode::Voltage);
+    rm_motors_can.start_loop(2_000).unwrap();
+    wait_for_iterations(&rm_motors_can, 10.0);
+    rm_motors_can.stop_loop().unwrap();
+    rm_motors_can.join_loop().unwrap();
+    let stat = |stat: LoopStat| rm_motors_can.get_loop_stat(stat).unwrap();
+    let iterations: f64 = stat(LoopStat::Iterations);
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(stat(LoopStat::Iterations), iterations, "the loop didn't stop");
+    // There is no feedback, so every run_once fails
+    assert_eq!(stat(LoopStat::Errors), iterations);
+    assert!(stat(LoopStat::MinPeriod) > 0.0);
+    assert!(stat(LoopStat::MinPeriod) <= stat(LoopStat::MeanPeriod) && stat(LoopStat::MeanPeriod) <= stat(LoopStat::MaxPeriod));
+    assert!(stat(LoopStat::MeanPeriod) > 1_000.0, "mean period {}us for a 2000us loop", stat(LoopStat::MeanPeriod));
+    assert!(stat(LoopStat::LastPeriod) > 0.0);
+}
+
+// A LoopbackTransport whose frames the kernel timestamped `delay` before they were read, and the adapter at `hardware`
+struct Timestamped {
+    inner:    LoopbackTransport,
+    delay:    Option<Duration>,
+    hardware: Option<Duration>,
+}
+
+impl CanTransport for Timestamped {
+    fn send(&self, frame: &CanFrame) -> io::Result<()> {
+        self.inner.send(frame)
+    }
+    fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<CanFrame>> {
+        self.inner.recv_timeout(timeout)
+    }
+    fn set_filters(&self, filters: &[CanFilter]) -> io::Result<()> {
+        self.inner.set_filters(filters)
+    }
+    fn recv_timestamped(&self, timeout: Duration) -> io::Result<Option<(CanFrame, FrameTimestamps)>> {
+        let kernel: Option<SystemTime> = self.delay.map(|delay| SystemTime::now() - delay);
+        Ok(self.recv_timeout(timeout)?.map(|frame| (frame, FrameTimestamps { kernel, hardware: self.hardware })))
+    }
+}
+
+// A bus that has received one feedback frame over a Timestamped transport
+fn timestamped_feedback(delay: Option<Duration>, hardware: Option<Duration>) -> Arc<RmMotorsCan> {
+    let (bus_end, motor_end): (LoopbackTransport, LoopbackTransport) = LoopbackTransport::pair();
+    let transport: Timestamped = Timestamped { inner: bus_end, delay, hardware };
+    let rm_motors_can: Arc<RmMotorsCan> = RmMotorsCan::builder().transport(Box::new(transport)).listen_time(LISTEN).build().unwrap();
+    rm_motors_can.init_motor(1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    send_feedback(&rm_motors_can, &motor_end, Feedback { temperature: 30, ..Default::default() });
+    rm_motors_can
+}
+
+#[test]
+fn kernel_timestamps_are_moved_onto_the_monotonic_clock() {
+    let delay: Duration = Duration::from_millis(200);
+    let rm_motors_can: Arc<RmMotorsCan> = timestamped_feedback(Some(delay), Some(Duration::from_micros(1234)));
+    let time: FeedbackTime = rm_motors_can.feedback_time(1).unwrap();
+    assert_eq!(time.source, TimestampSource::Kernel);
+    assert_eq!(time.hardware, Some(Duration::from_micros(1234)));
+    // The frame was received delay before it was read, and it was read just now
+    assert!(time.age >= delay && time.age < delay + TIMEOUT, "age {:?}", time.age);
+    let received: Duration = Instant::now() - time.received;
+    assert!(received >= time.age && received < time.age + TIMEOUT);
+    // The link goes by the same age, so 200ms after the frame it is already lost (see set_link_policy)
+    assert_eq!(rm_motors_can.link_status(1).unwrap(), LinkStatus::Lost);
+}
+
+#[test]
+fn feedback_without_a_usable_kernel_timestamp_is_stamped_when_read() {
+    // No timestamp, and one too old to be from this frame, which happens when the wall clock steps
+    for delay in [None, Some(Duration::from_secs(5))] {
+        let rm_motors_can: Arc<RmMotorsCan> = timestamped_feedback(delay, None);
+        let time: FeedbackTime = rm_motors_can.feedback_time(1).unwrap();
+        assert_eq!(time.source, TimestampSource::Userspace);
+        assert!(time.age < Duration::from_millis(50), "age {:?}", time.age);
+        assert_ne!(rm_motors_can.link_status(1).unwrap(), LinkStatus::Lost);
+    }
+}
+
+// The motor's commands in the voltage frames received since the last call, without waiting
+fn recv_voltage_commands(motor_end: &LoopbackTransport) -> Vec<i16> {
+    let mut commands: Vec<i16> = Vec::new();
+    while let Some(frame) = motor_end.recv_timeout(Duration::ZERO).unwrap() {
+        if protocol::data_frame_id(&frame) == Some(protocol::CMD_ID_V_L_6020) {
+            commands.push(protocol::decode_command(frame.data()).unwrap()[0]);
+        }
+    }
+    commands
+}
+
+#[test]
+fn estop_sends_zeros_in_every_command_frame() {
+    let (rm_motors_can, motor_end): (Arc<RmMotorsCan>, LoopbackTransport) = open(CmdMode::Voltage);
+    rm_motors_can.estop().unwrap();
+    let mut frames: Vec<(u16, [i16; 4])> = Vec::new();
+    while let Some(frame) = motor_end.recv_timeout(Duration::ZERO).unwrap() {
+        frames.push((protocol::data_frame_id(&frame).unwrap(), protocol::decode_command(frame.data()).unwrap()));
+    }
+    assert_eq!(frames, protocol::CMD_IDS.map(|frame_id| (frame_id, [0; 4])));
+    assert!(rm_motors_can.faulted().unwrap());
+    assert!(matches!(rm_motors_can.set_cmd(1, 1.0), Err(RmError::EStopped)));
+}
+
+#[test]
+fn the_loop_sends_zeros_until_clear_fault() {
+    let (rm_motors_can, motor_end): (Arc<RmMotorsCan>, LoopbackTransport) = open(CmdMode::Voltage);
+    send_feedback(&rm_motors_can, &motor_end, Feedback { temperature: 30, ..Default::default() });
+    rm_motors_can.set_cmd(1, 12.0).unwrap();
+    rm_motors_can.start_loop(1_000).unwrap();
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(recv_voltage_commands(&motor_end).last(), Some(&12500));
+    rm_motors_can.estop().unwrap();
+    // A frame the loop was already sending may arrive after estop's zeros, but it is followed by more zeros
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(recv_voltage_commands(&motor_end).last(), Some(&0));
+    thread::sleep(Duration::from_millis(20));
+    let commands: Vec<i16> = recv_voltage_commands(&motor_end);
+    assert!(commands.len() > 5 && commands.iter().all(|&command| command == 0), "{:?}", commands);
+    // The command from before the estop isn't resumed
+    rm_motors_can.clear_fault().unwrap();
+    assert!(!rm_motors_can.faulted().unwrap());
+    thread::sleep(Duration::from_millis(20));
+    let commands: Vec<i16> = recv_voltage_commands(&motor_end);
+    assert!(commands.len() > 5 && commands.iter().all(|&command| command == 0), "{:?}", commands);
+    rm_motors_can.set_cmd(1, 6.0).unwrap();
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(recv_voltage_commands(&motor_end).last(), Some(&6250));
+    rm_motors_can.stop_loop().unwrap();
+    rm_motors_can.join_loop().unwrap();
+}
+
+#[test]
+fn clear_fault_while_run_once_is_running() {
+    let (rm_motors_can, motor_end): (Arc<RmMotorsCan>, LoopbackTransport) = open(CmdMode::Voltage);
+    send_feedback(&rm_motors_can, &motor_end, Feedback { temperature: 30, ..Default::default() });
+    // Another thread calls run_once as fast as it can, so estop and clear_fault land at every point of it
+    let running: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
+    let runner: thread::JoinHandle<()> = {
+        let (rm_motors_can, running): (Arc<RmMotorsCan>, Arc<AtomicBool>) = (rm_motors_can.clone(), running.clone());
+        thread::spawn(move || while running.load(Ordering::Relaxed) {
+            let _ = rm_motors_can.run_once();
+        })
+    };
+    // A different command before each estop, so a frame built before one can be told apart from those built after
+    const CYCLES: i16 = 500;
+    for i in 1 ..= CYCLES {
+        // Sent as i, V_MAX being 25000
+        rm_motors_can.set_cmd(1, (i as f64 + 0.5)*V_MAX/25000.0).unwrap();
+        thread::yield_now();
+        rm_motors_can.estop().unwrap();
+        rm_motors_can.clear_fault().unwrap();
+        assert!(!rm_motors_can.faulted().unwrap());
+    }
+    running.store(false, Ordering::Relaxed);
+    runner.join().unwrap();
+
+    // estop's zeros in a frame run_once doesn't use mark where each estop was sent
+    let mut estops: i16 = 0;
+    let mut stale: Option<i16> = None;
+    while let Some(frame) = motor_end.recv_timeout(Duration::ZERO).unwrap() {
+        let command: i16 = protocol::decode_command(frame.data()).unwrap()[0];
+        match protocol::data_frame_id(&frame).unwrap() {
+            protocol::CMD_ID_I_H_6020 => estops += 1,
+            protocol::CMD_ID_V_L_6020 => {
+                // A command from before the latest estop may only have been on its way, and must be zeroed straight after
+                assert!(stale.is_none() || command == 0, "{:?} went out after estop {} and wasn't zeroed", stale, estops);
+                stale = (command != 0 && command <= estops).then_some(command);
+            },
+            _ => (),
+        }
+    }
+    assert_eq!(estops, CYCLES);
+    assert!(stale.is_none());
+}
@@ -0,0 +1,123 @@
+use embedded_can::{Frame, StandardId};
+use rm_motors_can::protocol::{self, Feedback};
+use rm_motors_can::{BusManager, CanTransport, CmdMode, FbField, LoopStat, LoopbackTransport, MotorSnapshot, MotorType, RmError, RmMotorsCan};
+use socketcan::CanFrame;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//////
+// BusManager over two LoopbackTransports, with the other ends playing the part of the motors:
+// a GM6020 with ID 1 on both buses, and an M3508 with ID 2 on bus 1
+//////
+
+const LISTEN: Duration = Duration::from_millis(10);
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+fn open() -> (Arc<BusManager>, [LoopbackTransport; 2]) {
+    let (bus_0, motors_0): (LoopbackTransport, LoopbackTransport) = LoopbackTransport::pair();
+    let (bus_1, motors_1): (LoopbackTransport, LoopbackTransport) = LoopbackTransport::pair();
+    let buses: Vec<Arc<RmMotorsCan>> = [bus_0, bus_1].into_iter()
+        .map(|bus_end| RmMotorsCan::builder().transport(Box::new(bus_end)).listen_time(LISTEN).build().unwrap())
+        .collect();
+    let manager: Arc<BusManager> = BusManager::new(buses);
+    manager.init_motor(0, 1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    manager.init_motor(1, 1, MotorType::GM6020, CmdMode::Voltage).unwrap();
+    manager.init_motor(1, 2, MotorType::M3508, CmdMode::Current).unwrap();
+    (manager, [motors_0, motors_1])
+}
+
+// Send feedback from a motor on a bus, and wait for its receive thread to have applied it
+fn send_feedback(manager: &BusManager, motor_ends: &[LoopbackTransport; 2], bus: usize, motor_type: MotorType, id: u8, temperature: u8) {
+    let feedback: Feedback = Feedback { temperature, ..Default::default() };
+    let frame: CanFrame = CanFrame::new(StandardId::new(protocol::feedback_id(motor_type, id)).unwrap(), &feedback.encode()).unwrap();
+    motor_ends[bus].send(&frame).unwrap();
+    let t: Instant = Instant::now();
+    while manager.get_state((bus, (motor_type, id)), FbField::Temperature).unwrap() != temperature as f64 {
+        assert!(t.elapsed() < TIMEOUT, "feedback wasn't received");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn send_all_feedback(manager: &BusManager, motor_ends: &[LoopbackTransport; 2]) {
+    send_feedback(manager, motor_ends, 0, MotorType::GM6020, 1, 30);
+    send_feedback(manager, motor_ends, 1, MotorType::GM6020, 1, 31);
+    send_feedback(manager, motor_ends, 1, MotorType::M3508, 2, 32);
+}
+
+// The commands of the frames with the given ID the motors of a bus have received since the last call
+fn recv_commands(motor_end: &LoopbackTransport, frame_id: u16) -> Vec<[i16; 4]> {
+    let mut commands: Vec<[i16; 4]> = Vec::new();
+    while let Some(frame) = motor_end.recv_timeout(Duration::ZERO).unwrap() {
+        if protocol::data_frame_id(&frame) == Some(frame_id) {
+            commands.push(protocol::decode_command(frame.data()).unwrap());
+        }
+    }
+    commands
+}
+
+#[test]
+fn motors_are_addressed_by_bus_and_id_or_by_name() {
+    let (manager, motor_ends): (Arc<BusManager>, [LoopbackTransport; 2]) = open();
+    send_all_feedback(&manager, &motor_ends);
+    manager.set_cmd((0, 1), 6.0).unwrap();
+    manager.set_cmd((1, 1), 12.0).unwrap();
+    manager.name_motor("yaw", 1, (MotorType::GM6020, 1)).unwrap();
+    manager.set_cmd("yaw", -3.0).unwrap();
+    assert_eq!(manager.get_state("yaw", FbField::Temperature).unwrap(), 31.0);
+    manager.run_once().unwrap();
+    assert_eq!(recv_commands(&motor_ends[0], protocol::CMD_ID_V_L_6020), [[6250, 0, 0, 0]]);
+    assert_eq!(recv_commands(&motor_ends[1], protocol::CMD_ID_V_L_6020), [[-3125, 0, 0, 0]]);
+    // A name moves to the motor it is given next
+    manager.name_motor("yaw", 0, 1).unwrap();
+    assert_eq!(manager.get_state("yaw", FbField::Temperature).unwrap(), 30.0);
+    assert!(matches!(manager.set_cmd("pitch", 1.0), Err(RmError::UnknownMotor(_))));
+    assert!(matches!(manager.set_cmd((2, 1), 1.0), Err(RmError::UnknownMotor(_))));
+    assert!(matches!(manager.name_motor("roll", 2, 1), Err(RmError::UnknownMotor(_))));
+}
+
+#[test]
+fn an_error_on_one_bus_doesnt_stop_the_others() {
+    let (manager, motor_ends): (Arc<BusManager>, [LoopbackTransport; 2]) = open();
+    // Bus 1 hasn't heard from its motors
+    send_feedback(&manager, &motor_ends, 0, MotorType::GM6020, 1, 30);
+    manager.set_cmd((0, 1), 6.0).unwrap();
+    assert!(matches!(manager.run_once(), Err(RmError::StaleFeedback { .. })));
+    assert_eq!(recv_commands(&motor_ends[0], protocol::CMD_ID_V_L_6020), [[6250, 0, 0, 0]]);
+    assert!(recv_commands(&motor_ends[1], protocol::CMD_ID_V_L_6020).is_empty());
+}
+
+#[test]
+fn one_loop_commands_every_bus() {
+    let (manager, motor_ends): (Arc<BusManager>, [LoopbackTransport; 2]) = open();
+    send_all_feedback(&manager, &motor_ends);
+    manager.set_cmd((0, 1), 6.0).unwrap();
+    manager.set_cmd((1, 2), 1.0).unwrap();
+    manager.start_loop(2_000).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    manager.stop_loop().unwrap();
+    manager.join_loop().unwrap();
+    let iterations: usize = manager.get_loop_stat(LoopStat::Iterations).unwrap() as usize;
+    assert!(iterations > 2);
+    // Every iteration sends on both buses
+    let bus_0: Vec<[i16; 4]> = recv_commands(&motor_ends[0], protocol::CMD_ID_V_L_6020);
+    let bus_1: Vec<[i16; 4]> = recv_commands(&motor_ends[1], protocol::CMD_ID_I_L_3508);
+    assert_eq!((bus_0.len(), bus_1.len()), (iterations, iterations));
+    assert!(bus_0.iter().all(|commands| commands[0] == 6250));
+    assert!(bus_1.iter().all(|commands| commands[1] == 819));
+}
+
+#[test]
+fn snapshot_covers_every_bus() {
+    let (manager, motor_ends): (Arc<BusManager>, [LoopbackTransport; 2]) = open();
+    send_all_feedback(&manager, &motor_ends);
+    let snapshot: Vec<MotorSnapshot> = manager.snapshot();
+    let motors: Vec<(usize, MotorType, u8, CmdMode, f64)> = snapshot.iter()
+        .map(|motor| (motor.bus, motor.motor_type, motor.id, motor.mode, motor.temperature))
+        .collect();
+    assert_eq!(motors, [
+        (0, MotorType::GM6020, 1, CmdMode::Voltage, 30.0),
+        (1, MotorType::GM6020, 1, CmdMode::Voltage, 31.0),
+        (1, MotorType::M3508,  2, CmdMode::Current, 32.0),
+    ]);
+}
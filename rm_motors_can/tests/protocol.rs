@@ -0,0 +1,164 @@
+use proptest::prelude::*;
+use rm_motors_can::protocol::{self, Feedback};
+use rm_motors_can::{id_max, CmdMode, Family, MotorType, ID_MIN};
+
+//////
+// The pure CAN protocol: frame IDs, motor ID resolution and the packing of values into frames.
+//////
+/*
+cargo test --test protocol
+*/
+
+const MOTOR_TYPES: [MotorType; 3] = [MotorType::GM6020, MotorType::M3508, MotorType::M2006];
+const MODES: [CmdMode; 6] = [CmdMode::Disabled, CmdMode::Voltage, CmdMode::Current, CmdMode::Torque, CmdMode::Velocity, CmdMode::Position];
+
+#[test]
+fn command_data_is_big_endian_in_id_order() {
+    assert_eq!(protocol::encode_command([0x0102, -1, 0, i16::MIN]), [0x01, 0x02, 0xff, 0xff, 0x00, 0x00, 0x80, 0x00]);
+    assert_eq!(protocol::decode_command(&[0x01, 0x02, 0xff, 0xff, 0x00, 0x00, 0x80, 0x00]), Some([0x0102, -1, 0, i16::MIN]));
+}
+
+#[test]
+fn feedback_data_layout() {
+    let feedback: Feedback = Feedback { position: 8191, velocity: -300, current: 0x1234, temperature: 40 };
+    assert_eq!(feedback.encode(), [0x1f, 0xff, 0xfe, 0xd4, 0x12, 0x34, 40, 0]);
+    assert_eq!(Feedback::decode(&[0x1f, 0xff, 0xfe, 0xd4, 0x12, 0x34, 40]), Some(feedback));
+}
+
+#[test]
+fn command_slot_table() {
+    // Every combination of type, mode and ID against the datasheets
+    for motor_type in MOTOR_TYPES {
+        for mode in MODES {
+            for id in 0 ..= u8::MAX {
+                let expected: Option<(u16, usize)> = match (motor_type, mode, id) {
+                    (MotorType::GM6020, CmdMode::Voltage, 1 ..= 4) => Some((0x1ff, id as usize - 1)),
+                    (MotorType::GM6020, CmdMode::Voltage, 5 ..= 7) => Some((0x2ff, id as usize - 5)),
+                    (MotorType::GM6020, CmdMode::Current, 1 ..= 4) => Some((0x1fe, id as usize - 1)),
+                    (MotorType::GM6020, CmdMode::Current, 5 ..= 7) => Some((0x2fe, id as usize - 5)),
+                    (MotorType::M3508 | MotorType::M2006, CmdMode::Current, 1 ..= 4) => Some((0x200, id as usize - 1)),
+                    (MotorType::M3508 | MotorType::M2006, CmdMode::Current, 5 ..= 8) => Some((0x1ff, id as usize - 5)),
+                    (_, _, _) => None,
+                };
+                assert_eq!(protocol::command_slot(motor_type, mode, id), expected, "{} {} {}", motor_type, mode, id);
+            }
+        }
+    }
+}
+
+#[test]
+fn command_slots_are_unique_per_type_and_drive() {
+    for motor_type in MOTOR_TYPES {
+        for drive in [CmdMode::Voltage, CmdMode::Current] {
+            let slots: Vec<(u16, usize)> = (ID_MIN ..= id_max(motor_type)).filter_map(|id| protocol::command_slot(motor_type, drive, id)).collect();
+            for (i, slot) in slots.iter().enumerate() {
+                assert!(slot.1 < 4);
+                assert!(!slots[i+1 ..].contains(slot), "{} {} {:?}", motor_type, drive, slot);
+            }
+        }
+    }
+}
+
+#[test]
+fn command_ids() {
+    for frame_id in 0 .. 0x800 {
+        let expected: bool = [0x1fe, 0x1ff, 0x200, 0x2fe, 0x2ff].contains(&frame_id);
+        assert_eq!(protocol::is_command_id(frame_id), expected, "{:#x}", frame_id);
+    }
+    // Every frame a motor can be commanded with is recognized, and no motor's feedback is mistaken for one
+    for motor_type in MOTOR_TYPES {
+        for id in ID_MIN ..= id_max(motor_type) {
+            for drive in [CmdMode::Voltage, CmdMode::Current] {
+                if let Some((frame_id, _)) = protocol::command_slot(motor_type, drive, id) {
+                    assert!(protocol::is_command_id(frame_id));
+                }
+            }
+            assert!(!protocol::is_command_id(protocol::feedback_id(motor_type, id)));
+        }
+    }
+}
+
+#[test]
+fn feedback_motor_id_table() {
+    for frame_id in 0 .. 0x800 {
+        for upper_3508 in [false, true] {
+            let expected: Option<u8> = match frame_id {
+                0x201 ..= 0x204 => Some((frame_id - 0x200) as u8),
+                0x205 ..= 0x208 if upper_3508 => Some((frame_id - 0x200) as u8),
+                0x205 ..= 0x20b => Some((frame_id - 0x204) as u8),
+                _ => None,
+            };
+            assert_eq!(protocol::feedback_motor_id(frame_id, upper_3508), expected, "{:#x} {}", frame_id, upper_3508);
+        }
+    }
+}
+
+#[test]
+fn feedback_id_resolves_to_the_motor() {
+    for id in ID_MIN ..= id_max(MotorType::GM6020) {
+        assert_eq!(protocol::feedback_motor(protocol::feedback_id(MotorType::GM6020, id), false), Some((Family::GM6020, id)));
+    }
+    for motor_type in [MotorType::M3508, MotorType::M2006] {
+        for id in ID_MIN ..= id_max(motor_type) {
+            assert_eq!(protocol::feedback_motor(protocol::feedback_id(motor_type, id), true), Some((Family::C6x0, id)));
+        }
+        // Without upper_3508 only the lower IDs are unambiguous
+        for id in ID_MIN ..= 4 {
+            assert_eq!(protocol::feedback_motor(protocol::feedback_id(motor_type, id), false), Some((Family::C6x0, id)));
+        }
+    }
+}
+
+#[test]
+fn short_frames_are_rejected() {
+    for len in 0 .. 8 {
+        assert_eq!(protocol::decode_command(&[0; 8][.. len]), None);
+    }
+    for len in 0 .. 7 {
+        assert_eq!(Feedback::decode(&[0; 8][.. len]), None);
+        assert_eq!(protocol::decode_feedback(0x205, &[0; 8][.. len], false), None);
+    }
+}
+
+fn feedback() -> impl Strategy<Value = Feedback> {
+    (any::<u16>(), any::<i16>(), any::<i16>(), any::<u8>())
+        .prop_map(|(position, velocity, current, temperature)| Feedback { position, velocity, current, temperature })
+}
+
+proptest! {
+    #[test]
+    fn command_round_trip(commands in any::<[i16; 4]>()) {
+        prop_assert_eq!(protocol::decode_command(&protocol::encode_command(commands)), Some(commands));
+    }
+
+    #[test]
+    fn command_ignores_extra_data(data in proptest::collection::vec(any::<u8>(), 8 .. 64)) {
+        let commands: [i16; 4] = protocol::decode_command(&data).unwrap();
+        prop_assert_eq!(&protocol::encode_command(commands)[..], &data[.. 8]);
+    }
+
+    #[test]
+    fn feedback_round_trip(feedback in feedback()) {
+        prop_assert_eq!(Feedback::decode(&feedback.encode()), Some(feedback));
+    }
+
+    #[test]
+    fn feedback_ignores_unused_bytes(data in proptest::collection::vec(any::<u8>(), 7 .. 64)) {
+        let feedback: Feedback = Feedback::decode(&data).unwrap();
+        prop_assert_eq!(&feedback.encode()[.. 7], &data[.. 7]);
+    }
+
+    #[test]
+    fn decode_feedback_is_id_and_values(frame_id in 0u16 .. 0x800, data in proptest::collection::vec(any::<u8>(), 0 .. 9), upper_3508 in any::<bool>()) {
+        let expected: Option<(u8, Feedback)> = protocol::feedback_motor_id(frame_id, upper_3508).zip(Feedback::decode(&data));
+        prop_assert_eq!(protocol::decode_feedback(frame_id, &data, upper_3508), expected);
+    }
+
+    #[test]
+    fn feedback_from_any_motor(motor_type in prop::sample::select(MOTOR_TYPES.to_vec()), id in ID_MIN ..= 8u8, feedback in feedback()) {
+        prop_assume!(id <= id_max(motor_type));
+        let upper_3508: bool = motor_type != MotorType::GM6020;
+        let frame_id: u16 = protocol::feedback_id(motor_type, id);
+        prop_assert_eq!(protocol::decode_feedback(frame_id, &feedback.encode(), upper_3508), Some((id, feedback)));
+    }
+}
@@ -0,0 +1,98 @@
+use embedded_can::Frame;
+use rm_motors_can::protocol::{self, Feedback};
+use rm_motors_can::{CanTransport, CmdMode, FbField, MotorType, RmError, RmMotorsCan, SimBus};
+use socketcan::CanFrame;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//////
+// The simulated bus, driven directly with command frames and through a "sim:" interface
+//////
+
+// Send one command to each motor, then return the latest feedback of each feedback ID after duration
+fn drive(sim: &SimBus, commands: &[(MotorType, CmdMode, u8, i16)], duration: Duration) -> HashMap<u16, Feedback> {
+    let mut frames: HashMap<u16, [i16; 4]> = HashMap::new();
+    for &(motor_type, drive, id, command) in commands {
+        let (frame_id, slot): (u16, usize) = protocol::command_slot(motor_type, drive, id).unwrap();
+        frames.entry(frame_id).or_default()[slot] = command;
+    }
+    for (&frame_id, &commands) in frames.iter() {
+        sim.send(&protocol::command_frame::<CanFrame>(frame_id, commands).unwrap()).unwrap();
+    }
+    let mut latest: HashMap<u16, Feedback> = HashMap::new();
+    let t: Instant = Instant::now();
+    while t.elapsed() < duration {
+        if let Some(frame) = sim.recv_timeout(Duration::from_millis(10)).unwrap() {
+            let frame_id: u16 = protocol::data_frame_id(&frame).unwrap();
+            latest.insert(frame_id, Feedback::decode(frame.data()).unwrap());
+        }
+    }
+    latest
+}
+
+#[test]
+fn commanded_motors_speed_up_in_the_commanded_direction() {
+    for sign in [1, -1] {
+        let sim: SimBus = SimBus::from_spec("GM6020:1,M3508:2").unwrap();
+        let commands: [(MotorType, CmdMode, u8, i16); 2] = [
+            (MotorType::GM6020, CmdMode::Voltage, 1, sign*12500), // 12V
+            (MotorType::M3508,  CmdMode::Current, 2, sign*4000),  // 4.9A
+        ];
+        let latest: HashMap<u16, Feedback> = drive(&sim, &commands, Duration::from_millis(200));
+        for motor_type in [MotorType::GM6020, MotorType::M3508] {
+            let id: u8 = if motor_type == MotorType::GM6020 { 1 } else { 2 };
+            let velocity: i16 = latest[&protocol::feedback_id(motor_type, id)].velocity;
+            assert!(velocity.signum() == sign && velocity.abs() > 50, "{}:{} turns at {}rpm", motor_type, id, velocity);
+        }
+    }
+}
+
+#[test]
+fn each_motor_reports_on_its_own_feedback_id() {
+    let sim: SimBus = SimBus::from_spec("GM6020:3,M3508:2,M2006:4").unwrap();
+    let latest: HashMap<u16, Feedback> = drive(&sim, &[], Duration::from_millis(20));
+    let mut ids: Vec<u16> = latest.keys().copied().collect();
+    ids.sort();
+    assert_eq!(ids, [0x202, 0x204, 0x207]);
+}
+
+#[test]
+fn uncommanded_motors_stay_still() {
+    let sim: SimBus = SimBus::from_spec("M3508:1").unwrap();
+    let latest: HashMap<u16, Feedback> = drive(&sim, &[(MotorType::GM6020, CmdMode::Voltage, 1, 12500)], Duration::from_millis(50));
+    // The GM6020 command frame also carries M3508 ID 5-8, not 1
+    assert_eq!(latest[&protocol::feedback_id(MotorType::M3508, 1)].velocity, 0);
+}
+
+#[test]
+fn bad_specs_are_rejected() {
+    for spec in ["GM6020", "GM6020:x", "GM9999:1", "M3508:1,"] {
+        assert!(matches!(SimBus::from_spec(spec), Err(RmError::InvalidInterface(_))), "{}", spec);
+    }
+    assert!(matches!(SimBus::from_spec("GM6020:8"), Err(RmError::IdOutOfRange { id: 8, .. })));
+    // Same feedback ID
+    assert!(matches!(SimBus::from_spec("GM6020:1,M3508:5"), Err(RmError::InvalidInterface(_))));
+    assert!(matches!(RmMotorsCan::open("sim:GM6020"), Err(RmError::InvalidInterface(_))));
+}
+
+#[test]
+fn sim_interface_drives_a_motor() {
+    // Plain "sim" is a single GM6020 with ID 1
+    for interface in ["sim", "sim:M3508:2"] {
+        let rm_motors_can: Arc<RmMotorsCan> = RmMotorsCan::open(interface).unwrap();
+        let (motor_type, id, mode, cmd): (MotorType, u8, CmdMode, f64) = match interface {
+            "sim" => (MotorType::GM6020, 1, CmdMode::Voltage, -6.0),
+            _ => (MotorType::M3508, 2, CmdMode::Current, -3.0),
+        };
+        rm_motors_can.init_motor(id, motor_type, mode).unwrap();
+        rm_motors_can.set_cmd(id, cmd).unwrap();
+        let t: Instant = Instant::now();
+        while t.elapsed() < Duration::from_millis(200) {
+            rm_motors_can.run_once().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(rm_motors_can.get_state(id, FbField::Velocity).unwrap() < -1.0, "{}", interface);
+    }
+}
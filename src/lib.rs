@@ -2,60 +2,415 @@ use rm_motors_can::*;
 
 use std::ffi::CStr;
 use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::mem::ManuallyDrop;
 use std::ptr::null;
 use std::sync::Arc;
 
-/*
-**  interface: SocketCAN interface name e.g. "can0"
-**  returns: pointer to RmMotorsCan struct, to be passed to other functions in this library
-*/
+// Error codes returned by the functions below. Functions reading a double write it through their out pointer and return 0.
+pub const RM_ERR_INVALID_ARG: i32            = -1;  // Null handle or out pointer, or invalid string
+pub const RM_ERR_IO: i32                     = -2;
+pub const RM_ERR_NOT_INITIALIZED: i32        = -3;
+pub const RM_ERR_INVALID_INTERFACE: i32      = -4;
+pub const RM_ERR_ID_OUT_OF_RANGE: i32        = -5;
+pub const RM_ERR_ID_COLLISION: i32           = -6;
+pub const RM_ERR_UNSUPPORTED_MODE: i32       = -7;
+pub const RM_ERR_OVER_TEMPERATURE: i32       = -8;
+pub const RM_ERR_UNSUPPORTED_FIELD: i32      = -9;
+pub const RM_ERR_STALE_FEEDBACK: i32         = -10;
+pub const RM_ERR_CONFLICTING_CONTROLLER: i32 = -11;
+pub const RM_ERR_PANIC: i32                  = -12; // Bug in the library, please report it
+pub const RM_ERR_INVALID_PARAMETER: i32      = -13;
+pub const RM_ERR_CAN: i32                    = -14;
+pub const RM_ERR_AMBIGUOUS_ID: i32           = -15; // Motors of both families have the ID, use the _typed function
+pub const RM_ERR_UNKNOWN_MOTOR: i32          = -16; // No bus at the index, or no motor with the name
+pub const RM_ERR_COMMAND_TIMEOUT: i32        = -17; // No set_cmd within the motor's command timeout, so run_once stopped it
+pub const RM_ERR_LINK_FAULT: i32             = -18; // The motor's link was lost and its policy stopped it, see clear_link_fault
+pub const RM_ERR_ESTOPPED: i32               = -19; // The bus was emergency stopped, see clear_fault
+
+fn error_code(err: &RmError) -> i32 {
+    match err {
+        RmError::Io(_)                   => RM_ERR_IO,
+        RmError::Can(_)                  => RM_ERR_CAN,
+        RmError::NotInitialized          => RM_ERR_NOT_INITIALIZED,
+        RmError::InvalidInterface(_)     => RM_ERR_INVALID_INTERFACE,
+        RmError::IdOutOfRange {..}       => RM_ERR_ID_OUT_OF_RANGE,
+        RmError::IdCollision {..}        => RM_ERR_ID_COLLISION,
+        RmError::AmbiguousId {..}        => RM_ERR_AMBIGUOUS_ID,
+        RmError::UnsupportedMode {..}    => RM_ERR_UNSUPPORTED_MODE,
+        RmError::OverTemperature {..}    => RM_ERR_OVER_TEMPERATURE,
+        RmError::UnsupportedField {..}   => RM_ERR_UNSUPPORTED_FIELD,
+        RmError::StaleFeedback {..}      => RM_ERR_STALE_FEEDBACK,
+        RmError::ConflictingController   => RM_ERR_CONFLICTING_CONTROLLER,
+        RmError::InvalidParameter {..}   => RM_ERR_INVALID_PARAMETER,
+        RmError::UnknownMotor(_)         => RM_ERR_UNKNOWN_MOTOR,
+        RmError::CommandTimeout {..}     => RM_ERR_COMMAND_TIMEOUT,
+        RmError::LinkFault {..}          => RM_ERR_LINK_FAULT,
+        RmError::EStopped                => RM_ERR_ESTOPPED,
+    }
+}
+
+// What the Ok value of a method becomes in C: 0 for success, or the value itself
+trait CValue<T> { fn c_value(self) -> T; }
+impl CValue<i32> for ()         { fn c_value(self) -> i32 { 0 } }
+impl CValue<i32> for MoveStatus { fn c_value(self) -> i32 { self as i32 } }
+impl CValue<i32> for bool       { fn c_value(self) -> i32 { self as i32 } }
+impl CValue<i32> for LinkStatus { fn c_value(self) -> i32 { self as i32 } }
+impl CValue<i32> for MotorStatus { fn c_value(self) -> i32 { self.0 as i32 } }
+impl CValue<i32> for usize      { fn c_value(self) -> i32 { self as i32 } }
+
+// Write the value of a getter through the out pointer from C, which the wrapper has checked isn't null
+fn write_out<T>(out: *mut T, value: T) -> i32 {
+    unsafe { *out = value };
+    0
+}
+
+// RmMotorsCan::feedback_time in seconds, since C doesn't have an Instant
+trait FeedbackTimeSecs {
+    // CLOCK_MONOTONIC, the clock of std::chrono::steady_clock
+    fn get_feedback_timestamp(&self, id: impl Into<MotorId>) -> Result<f64, RmError>;
+    fn get_feedback_age(&self, id: impl Into<MotorId>) -> Result<f64, RmError>;
+}
+impl FeedbackTimeSecs for RmMotorsCan {
+    fn get_feedback_timestamp(&self, id: impl Into<MotorId>) -> Result<f64, RmError> {
+        Ok(self.feedback_time(id)?.monotonic().as_secs_f64())
+    }
+    fn get_feedback_age(&self, id: impl Into<MotorId>) -> Result<f64, RmError> {
+        Ok(self.feedback_time(id)?.age.as_secs_f64())
+    }
+}
+
+// fault_history into an array from C, up to capacity records. Returns the number of records, which may be more than capacity.
+trait CopyFaultHistory<A> {
+    fn copy_fault_history(&self, motor: A, out: *mut FaultRecord, capacity: usize) -> Result<usize, RmError>;
+}
+fn copy_records(history: Vec<FaultRecord>, out: *mut FaultRecord, capacity: usize) -> Result<usize, RmError> {
+    if out.is_null() && capacity > 0 {
+        return Err(RmError::InvalidParameter { name: "out", value: 0.0 });
+    }
+    for (i, record) in history.iter().take(capacity).enumerate() {
+        unsafe { *out.add(i) = *record };
+    }
+    Ok(history.len())
+}
+impl<A: Into<MotorId>> CopyFaultHistory<A> for RmMotorsCan {
+    fn copy_fault_history(&self, motor: A, out: *mut FaultRecord, capacity: usize) -> Result<usize, RmError> {
+        copy_records(self.fault_history(motor)?, out, capacity)
+    }
+}
+impl<'a, A: Into<MotorAddr<'a>>> CopyFaultHistory<A> for BusManager {
+    fn copy_fault_history(&self, motor: A, out: *mut FaultRecord, capacity: usize) -> Result<usize, RmError> {
+        copy_records(self.fault_history(motor)?, out, capacity)
+    }
+}
+
+/// interface: SocketCAN interface name e.g. "can0"
+/// returns: pointer to RmMotorsCan struct, to be passed to other functions in this library, or null on error
+///
+/// # Safety
+/// interface must be null or a nul-terminated string.
 #[no_mangle]
-pub extern "C" fn init_bus(interface: *const c_char) -> *mut RmMotorsCan {
-    let inter: &str;
+pub unsafe extern "C" fn init_bus(interface: *const c_char) -> *mut RmMotorsCan {
     if interface.is_null() {
-        println!("Invalid c-string received for interface name (null pointer)");
+        eprintln!("Invalid c-string received for interface name (null pointer)");
         return null::<RmMotorsCan>() as *mut RmMotorsCan;
     }
-    else {
-        unsafe {
-            let r = CStr::from_ptr(interface).to_str();
-            if r.is_err() {
-                eprintln!("Invalid c-string received for interface name");
-                return null::<RmMotorsCan>() as *mut RmMotorsCan;
+    let inter: &str = match unsafe { CStr::from_ptr(interface) }.to_str() {
+        Ok(inter) => inter,
+        Err(_) => {
+            eprintln!("Invalid c-string received for interface name");
+            return null::<RmMotorsCan>() as *mut RmMotorsCan;
+        }
+    };
+    // Unwinding across the FFI boundary is undefined behaviour, so catch any panic here
+    match catch_unwind(|| RmMotorsCan::open(inter)) {
+        Ok(Ok(v))  => Arc::into_raw(v) as *mut RmMotorsCan,
+        Ok(Err(e)) => {eprintln!("{}", e); null::<RmMotorsCan>() as *mut RmMotorsCan},
+        Err(_)     => {eprintln!("Panic in init_bus"); null::<RmMotorsCan>() as *mut RmMotorsCan},
+    }
+}
+
+/// Free an RmMotorsCan from init_bus. Call cleanup first to stop the motors and the I/O loop.
+///
+/// # Safety
+/// rm_motors_can must be null or from init_bus, and not used again after this. Don't pass one from manager_bus, see free_manager.
+#[no_mangle]
+pub unsafe extern "C" fn free_bus(rm_motors_can: *mut RmMotorsCan) {
+    if !rm_motors_can.is_null() {
+        drop(unsafe { Arc::from_raw(rm_motors_can as *const RmMotorsCan) });
+    }
+}
+
+// A string from C, "" if it is null or not UTF-8
+fn c_str<'a>(s: *const c_char) -> &'a str {
+    if s.is_null() {
+        return "";
+    }
+    unsafe { CStr::from_ptr(s) }.to_str().unwrap_or("")
+}
+
+/// interfaces: array of count interface names, see init_bus. Each bus's index is its position in the array.
+/// returns: pointer to BusManager struct, to be passed to the manager_ functions in this library, or null on error.
+/// Free it with free_manager.
+///
+/// # Safety
+/// interfaces must be null or point to count pointers, each null or a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn init_manager(interfaces: *const *const c_char, count: usize) -> *mut BusManager {
+    if interfaces.is_null() {
+        eprintln!("Invalid array received for interface names (null pointer)");
+        return null::<BusManager>() as *mut BusManager;
+    }
+    let mut inters: Vec<&str> = Vec::with_capacity(count);
+    for i in 0..count {
+        let interface: *const c_char = unsafe { *interfaces.add(i) };
+        match (!interface.is_null()).then(|| unsafe { CStr::from_ptr(interface) }.to_str()) {
+            Some(Ok(inter)) => inters.push(inter),
+            _ => {
+                eprintln!("Invalid c-string received for interface name {}", i);
+                return null::<BusManager>() as *mut BusManager;
             }
-            inter = r.unwrap();
         }
     }
-    rm_motors_can::init_bus(inter).map_or_else(|e| {eprintln!("{}", e); null::<RmMotorsCan>() as *const RmMotorsCan}, |v| Arc::into_raw(v)) as *mut RmMotorsCan
+    // Unwinding across the FFI boundary is undefined behaviour, so catch any panic here
+    match catch_unwind(|| BusManager::open(&inters)) {
+        Ok(Ok(v))  => Arc::into_raw(v) as *mut BusManager,
+        Ok(Err(e)) => {eprintln!("{}", e); null::<BusManager>() as *mut BusManager},
+        Err(_)     => {eprintln!("Panic in init_manager"); null::<BusManager>() as *mut BusManager},
+    }
+}
+
+/// Free a BusManager from init_manager, along with its buses. Call manager_cleanup first to stop the motors.
+///
+/// # Safety
+/// manager must be null or from init_manager, and not used again after this, nor any RmMotorsCan from its manager_bus.
+#[no_mangle]
+pub unsafe extern "C" fn free_manager(manager: *mut BusManager) {
+    if !manager.is_null() {
+        drop(unsafe { Arc::from_raw(manager as *const BusManager) });
+    }
+}
+
+/// returns: the RmMotorsCan of a bus, for the functions the manager doesn't have e.g. set_velocity_gains,
+/// or null if there is no such bus.
+/// The pointer is borrowed from the manager: don't free it, and don't use it after free_manager.
+///
+/// # Safety
+/// manager must be null or from init_manager and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn manager_bus(manager: *mut BusManager, bus: usize) -> *mut RmMotorsCan {
+    if manager.is_null() {
+        eprintln!("Invalid handle (null pointer)");
+        return null::<RmMotorsCan>() as *mut RmMotorsCan;
+    }
+    // Unwinding across the FFI boundary is undefined behaviour, so catch any panic here
+    match catch_unwind(AssertUnwindSafe(|| unsafe { &*manager }.bus(bus).map(Arc::as_ptr))) {
+        Ok(Ok(rm_motors_can)) => rm_motors_can as *mut RmMotorsCan,
+        Ok(Err(e))            => {eprintln!("{}", e); null::<RmMotorsCan>() as *mut RmMotorsCan},
+        Err(_)                => {eprintln!("Panic in manager_bus"); null::<RmMotorsCan>() as *mut RmMotorsCan},
+    }
+}
+
+/// Copy the state of the initialized motors of every bus into out, up to capacity of them
+/// returns: the number of initialized motors, which may be more than capacity, or an error code
+///
+/// # Safety
+/// manager must be null or from init_manager and not yet freed, and out must have room for capacity motors.
+#[no_mangle]
+pub unsafe extern "C" fn manager_snapshot(manager: *mut BusManager, out: *mut MotorSnapshot, capacity: usize) -> i32 {
+    if manager.is_null() || (out.is_null() && capacity > 0) {
+        eprintln!("Invalid handle (null pointer)");
+        return RM_ERR_INVALID_ARG;
+    }
+    match catch_unwind(AssertUnwindSafe(|| unsafe { &*manager }.snapshot())) {
+        Ok(snapshot) => {
+            for (i, motor) in snapshot.iter().take(capacity).enumerate() {
+                unsafe { *out.add(i) = *motor };
+            }
+            snapshot.len() as i32
+        },
+        Err(_) => {eprintln!("Panic in manager_snapshot"); RM_ERR_PANIC},
+    }
 }
 
 macro_rules! generate_wrapper {
-    ($func_name:ident, ($($param_name:ident: $param_type:ty),*), $return_type:ty) => {
+    // What an Ok value becomes: see CValue, or 0 once it is written through the out pointer
+    (@ok $v:ident) => { $v.c_value() };
+    (@ok $v:ident => $out:ident) => { write_out($out, $v) };
+    ($func_name:ident, ($($param_name:ident: $param_type:ty),*), $return_type:tt) => {
+        generate_wrapper!($func_name => $func_name, ($($param_name: $param_type),*), ($($param_name),*), $return_type);
+    };
+    // The motor given by type and ID, for IDs that motors of both families have e.g. set_cmd_typed(bus, GM6020, 5, cmd)
+    (typed $func_name:ident => $method:ident, ($($param_name:ident: $param_type:ty),*), $return_type:tt) => {
+        generate_wrapper!($func_name => $method, (motor_type: MotorType, id: u8 $(, $param_name: $param_type)*), ((motor_type, id) $(, $param_name)*), $return_type);
+    };
+    // The motor given by bus and ID of a BusManager e.g. manager_set_cmd(manager, 1, 5, cmd)
+    (manager $func_name:ident => $method:ident, ($($param_name:ident: $param_type:ty),*), $return_type:tt) => {
+        generate_wrapper!(@ manager: BusManager, $func_name => $method, (bus: usize, id: u8 $(, $param_name: $param_type)*), ((bus, id) $(, $param_name)*), $return_type);
+    };
+    // The motor given by the name from manager_name_motor e.g. manager_set_cmd_named(manager, "yaw", cmd)
+    (named $func_name:ident => $method:ident, ($($param_name:ident: $param_type:ty),*), $return_type:tt) => {
+        generate_wrapper!(@ manager: BusManager, $func_name => $method, (name: *const c_char $(, $param_name: $param_type)*), (c_str(name) $(, $param_name)*), $return_type);
+    };
+    ($func_name:ident => $method:ident, ($($param_name:ident: $param_type:ty),*), ($($arg:expr),*), $return_type:tt) => {
+        generate_wrapper!(@ rm_motors_can: RmMotorsCan, $func_name => $method, ($($param_name: $param_type),*), ($($arg),*), $return_type);
+    };
+    // A double can't hold an error code without it looking like a value, so it is written through an out pointer after the other parameters
+    // e.g. get_state(bus, 1, FbField::Velocity, &velocity) returns 0 or an error code
+    (@ $handle:ident: $handle_type:ty, $func_name:ident => $method:ident, ($($param_name:ident: $param_type:ty),*), ($($arg:expr),*), f64) => {
+        generate_wrapper!(@ $handle: $handle_type, $func_name => $method, ($($param_name: $param_type,)* out: *mut f64), ($($arg),*) => out, i32);
+    };
+    (@ $handle:ident: $handle_type:ty, $func_name:ident => $method:ident, ($($param_name:ident: $param_type:ty),*), ($($arg:expr),*) $(=> $out:ident)?, $return_type:tt) => {
+        /// # Safety
+        /// The handle must be null or not yet freed, from init_bus or manager_bus, or from init_manager for the manager_ functions.
+        /// Other pointers must be null or valid: strings nul-terminated, and out pointers writable (for capacity records).
         #[no_mangle]
-        pub extern "C" fn $func_name(rm_motors_can: *mut RmMotorsCan, $($param_name: $param_type),*) -> $return_type {
-            if rm_motors_can.is_null(){
-                println!("Invalid handle (null pointer)");
-                return -1 as $return_type;
+        pub unsafe extern "C" fn $func_name($handle: *mut $handle_type, $($param_name: $param_type),*) -> $return_type {
+            if $handle.is_null(){
+                eprintln!("Invalid handle (null pointer)");
+                return RM_ERR_INVALID_ARG as $return_type;
             }
+            $(if $out.is_null(){
+                eprintln!("Invalid out pointer (null pointer)");
+                return RM_ERR_INVALID_ARG as $return_type;
+            })?
 
-            let rm_motors_can: Arc<RmMotorsCan> = unsafe { Arc::from_raw(rm_motors_can as *const RmMotorsCan) }; // reconstitute the Arc temporarily to clone it
-            let rm_motors_can_ref2 = Arc::clone(&rm_motors_can);
-            std::mem::forget(rm_motors_can); // "forget" the Arc to avoid dropping it (since C++ still needs to reuse it)
-            rm_motors_can::$func_name(rm_motors_can_ref2, $($param_name),*).map_or_else(|e| {eprintln!("{}", e); -1 as $return_type}, |v| v as $return_type)
+            // Borrow the Arc that C++ holds without taking ownership of it, so it isn't dropped at the end of this call
+            let $handle: ManuallyDrop<Arc<$handle_type>> = ManuallyDrop::new(unsafe { Arc::from_raw($handle as *const $handle_type) });
+            // Unwinding across the FFI boundary is undefined behaviour, so catch any panic here
+            match catch_unwind(AssertUnwindSafe(|| $handle.$method($($arg),*))) {
+                Ok(Ok(v))  => generate_wrapper!(@ok v $(=> $out)?),
+                Ok(Err(e)) => {eprintln!("{}", e); error_code(&e) as $return_type},
+                Err(_)     => {eprintln!("Panic in {}", stringify!($func_name)); RM_ERR_PANIC as $return_type},
+            }
         }
     };
 }
 
+/*
+**  Emergency stop, see RmMotorsCan::estop. Unlike the generated wrappers it doesn't print errors, only returns their code,
+**  so it is safe to call from a signal handler as long as the transport's send doesn't lock or allocate (a SocketCAN interface only writes to its socket).
+*/
+fn estop_handle<H>(handle: *mut H, estop: fn(&H) -> Result<(), RmError>) -> i32 {
+    if handle.is_null() {
+        return RM_ERR_INVALID_ARG;
+    }
+    let handle: ManuallyDrop<Arc<H>> = ManuallyDrop::new(unsafe { Arc::from_raw(handle as *const H) });
+    match catch_unwind(AssertUnwindSafe(|| estop(&handle))) {
+        Ok(Ok(()))  => 0,
+        Ok(Err(e))  => error_code(&e),
+        Err(_)      => RM_ERR_PANIC,
+    }
+}
+/// # Safety
+/// rm_motors_can must be null or from init_bus or manager_bus and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn estop(rm_motors_can: *mut RmMotorsCan) -> i32 {
+    estop_handle(rm_motors_can, RmMotorsCan::estop)
+}
+/// # Safety
+/// manager must be null or from init_manager and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn manager_estop(manager: *mut BusManager) -> i32 {
+    estop_handle(manager, BusManager::estop)
+}
+
 generate_wrapper!(init_motor, (id: u8, motor_type: MotorType, mode: CmdMode), i32);
+generate_wrapper!(init_motor_geared, (id: u8, motor_type: MotorType, mode: CmdMode, gear_ratio: f64), i32);
 generate_wrapper!(cleanup,    (period_ms: u64), i32);
 generate_wrapper!(run_once,   (), i32);
 generate_wrapper!(set_cmd,    (id: u8, cmd: f64), i32);
 generate_wrapper!(get_state,  (id: u8, field: FbField), f64);
+generate_wrapper!(zero_position, (id: u8), i32);
+generate_wrapper!(set_position_gains, (id: u8, kp: f64, ki: f64, kd: f64), i32);
+generate_wrapper!(set_velocity_gains, (id: u8, kp: f64, ki: f64, kf: f64), i32);
+generate_wrapper!(set_stage_gains, (id: u8, stage: Stage, kp: f64, ki: f64, kd: f64, kf: f64), i32);
+generate_wrapper!(set_stage_limit, (id: u8, stage: Stage, limit: f64), i32);
+generate_wrapper!(set_feedforward, (id: u8, stage: Stage, value: f64), i32);
+generate_wrapper!(get_stage_signal, (id: u8, stage: Stage, signal: StageSignal), f64);
+generate_wrapper!(move_to, (id: u8, target: f64, max_vel: f64, max_accel: f64), i32);
+generate_wrapper!(move_to_s_curve, (id: u8, target: f64, max_vel: f64, max_accel: f64, max_jerk: f64), i32);
+generate_wrapper!(move_status, (id: u8), i32); // MoveStatus, or an error code
+generate_wrapper!(cancel_move, (id: u8), i32);
+generate_wrapper!(start_loop, (period_us: u64), i32);
+generate_wrapper!(stop_loop,  (), i32);
+generate_wrapper!(join_loop,  (), i32);
+generate_wrapper!(get_loop_stat, (stat: LoopStat), f64);
+generate_wrapper!(get_feedback_timestamp, (id: u8), f64);
+generate_wrapper!(get_feedback_age, (id: u8), f64);
+generate_wrapper!(set_command_timeout, (id: u8, timeout_ms: u64, ramp_ms: u64), i32);
+generate_wrapper!(command_timed_out, (id: u8), i32); // 1 or 0, or an error code
+generate_wrapper!(set_link_policy, (id: u8, policy: LinkPolicy, timeout_ms: u64), i32);
+generate_wrapper!(link_status, (id: u8), i32); // LinkStatus, or an error code
+generate_wrapper!(clear_link_fault, (id: u8), i32);
+generate_wrapper!(faulted, (), i32); // 1 or 0
+generate_wrapper!(motor_status, (id: u8), i32); // MotorStatus bits, or an error code
+generate_wrapper!(fault_history => copy_fault_history, (id: u8, out: *mut FaultRecord, capacity: usize), (id, out, capacity), i32); // Number of records, or an error code
+generate_wrapper!(clear_faults, (id: u8), i32);
+generate_wrapper!(set_temperature_limits, (id: u8, derate_c: u8, max_c: u8), i32);
+generate_wrapper!(clear_fault, (), i32);
+generate_wrapper!(typed set_cmd_typed => set_cmd, (cmd: f64), i32);
+generate_wrapper!(typed get_state_typed => get_state, (field: FbField), f64);
+generate_wrapper!(typed zero_position_typed => zero_position, (), i32);
+generate_wrapper!(typed set_position_gains_typed => set_position_gains, (kp: f64, ki: f64, kd: f64), i32);
+generate_wrapper!(typed set_velocity_gains_typed => set_velocity_gains, (kp: f64, ki: f64, kf: f64), i32);
+generate_wrapper!(typed set_stage_gains_typed => set_stage_gains, (stage: Stage, kp: f64, ki: f64, kd: f64, kf: f64), i32);
+generate_wrapper!(typed set_stage_limit_typed => set_stage_limit, (stage: Stage, limit: f64), i32);
+generate_wrapper!(typed set_feedforward_typed => set_feedforward, (stage: Stage, value: f64), i32);
+generate_wrapper!(typed get_stage_signal_typed => get_stage_signal, (stage: Stage, signal: StageSignal), f64);
+generate_wrapper!(typed move_to_typed => move_to, (target: f64, max_vel: f64, max_accel: f64), i32);
+generate_wrapper!(typed move_to_s_curve_typed => move_to_s_curve, (target: f64, max_vel: f64, max_accel: f64, max_jerk: f64), i32);
+generate_wrapper!(typed move_status_typed => move_status, (), i32);
+generate_wrapper!(typed cancel_move_typed => cancel_move, (), i32);
+generate_wrapper!(typed get_feedback_timestamp_typed => get_feedback_timestamp, (), f64);
+generate_wrapper!(typed get_feedback_age_typed => get_feedback_age, (), f64);
+generate_wrapper!(typed set_command_timeout_typed => set_command_timeout, (timeout_ms: u64, ramp_ms: u64), i32);
+generate_wrapper!(typed command_timed_out_typed => command_timed_out, (), i32);
+generate_wrapper!(typed set_link_policy_typed => set_link_policy, (policy: LinkPolicy, timeout_ms: u64), i32);
+generate_wrapper!(typed link_status_typed => link_status, (), i32);
+generate_wrapper!(typed clear_link_fault_typed => clear_link_fault, (), i32);
+generate_wrapper!(typed motor_status_typed => motor_status, (), i32);
+generate_wrapper!(typed fault_history_typed => copy_fault_history, (out: *mut FaultRecord, capacity: usize), i32);
+generate_wrapper!(typed clear_faults_typed => clear_faults, (), i32);
+generate_wrapper!(typed set_temperature_limits_typed => set_temperature_limits, (derate_c: u8, max_c: u8), i32);
+generate_wrapper!(@ manager: BusManager, manager_init_motor => init_motor, (bus: usize, id: u8, motor_type: MotorType, mode: CmdMode), (bus, id, motor_type, mode), i32);
+generate_wrapper!(@ manager: BusManager, manager_init_motor_geared => init_motor_geared, (bus: usize, id: u8, motor_type: MotorType, mode: CmdMode, gear_ratio: f64), (bus, id, motor_type, mode, gear_ratio), i32);
+generate_wrapper!(@ manager: BusManager, manager_name_motor => name_motor, (name: *const c_char, bus: usize, motor_type: MotorType, id: u8), (c_str(name), bus, (motor_type, id)), i32);
+generate_wrapper!(@ manager: BusManager, manager_cleanup => cleanup, (period_ms: u64), (period_ms), i32);
+generate_wrapper!(@ manager: BusManager, manager_run_once => run_once, (), (), i32);
+generate_wrapper!(@ manager: BusManager, manager_start_loop => start_loop, (period_us: u64), (period_us), i32);
+generate_wrapper!(@ manager: BusManager, manager_stop_loop => stop_loop, (), (), i32);
+generate_wrapper!(@ manager: BusManager, manager_join_loop => join_loop, (), (), i32);
+generate_wrapper!(@ manager: BusManager, manager_get_loop_stat => get_loop_stat, (stat: LoopStat), (stat), f64);
+generate_wrapper!(@ manager: BusManager, manager_faulted => faulted, (), (), i32);
+generate_wrapper!(@ manager: BusManager, manager_clear_fault => clear_fault, (), (), i32);
+generate_wrapper!(manager manager_set_cmd => set_cmd, (cmd: f64), i32);
+generate_wrapper!(manager manager_get_state => get_state, (field: FbField), f64);
+generate_wrapper!(manager manager_zero_position => zero_position, (), i32);
+generate_wrapper!(manager manager_move_to => move_to, (target: f64, max_vel: f64, max_accel: f64), i32);
+generate_wrapper!(manager manager_move_to_s_curve => move_to_s_curve, (target: f64, max_vel: f64, max_accel: f64, max_jerk: f64), i32);
+generate_wrapper!(manager manager_move_status => move_status, (), i32);
+generate_wrapper!(manager manager_cancel_move => cancel_move, (), i32);
+generate_wrapper!(manager manager_motor_status => motor_status, (), i32);
+generate_wrapper!(manager manager_fault_history => copy_fault_history, (out: *mut FaultRecord, capacity: usize), i32);
+generate_wrapper!(manager manager_clear_faults => clear_faults, (), i32);
+generate_wrapper!(named manager_set_cmd_named => set_cmd, (cmd: f64), i32);
+generate_wrapper!(named manager_get_state_named => get_state, (field: FbField), f64);
+generate_wrapper!(named manager_zero_position_named => zero_position, (), i32);
+generate_wrapper!(named manager_move_to_named => move_to, (target: f64, max_vel: f64, max_accel: f64), i32);
+generate_wrapper!(named manager_move_to_s_curve_named => move_to_s_curve, (target: f64, max_vel: f64, max_accel: f64, max_jerk: f64), i32);
+generate_wrapper!(named manager_move_status_named => move_status, (), i32);
+generate_wrapper!(named manager_cancel_move_named => cancel_move, (), i32);
+generate_wrapper!(named manager_motor_status_named => motor_status, (), i32);
+generate_wrapper!(named manager_fault_history_named => copy_fault_history, (out: *mut FaultRecord, capacity: usize), i32);
+generate_wrapper!(named manager_clear_faults_named => clear_faults, (), i32);
 
 
 #[link(name = "rm_motors_can_test_cpp")]
 extern "C" { fn rm_motors_can_test_cpp() -> i32; }
 // TODO this is only here due to a bug in the cc crate preventing c++ in examples: https://github.com/rust-lang/cc-rs/issues/1206
+/// # Safety
+/// Runs the C++ example, which exits the process.
 pub unsafe fn cpp_example(){
     std::process::exit(rm_motors_can_test_cpp());
 }